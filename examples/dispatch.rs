@@ -0,0 +1,67 @@
+//! Times the executor's dispatch loop on two workloads:
+//!
+//! * `loop`: the arithmetic loop from `examples/loop.asm`, run for more iterations.
+//! * `print`: the `python_comparison/t1.py` workload, printing two lines a thousand times.
+//!
+//! Run with `cargo run --release --example dispatch > /dev/null`; timings go to stderr.
+use std::time::{Duration, Instant};
+
+use interpreted_language::lexer::asm::Parser;
+use interpreted_language::vm::vm::Executor;
+
+const LOOP:&str = "
+    NUM ind 0
+    NUM sum 0
+    NUM inc 1
+    NUM adder -1.289893
+    NUM itterations 100000
+
+    BLOCK loop
+        MUL additive ind adder
+        ADD sum sum additive
+
+    ADD ind ind inc
+    LT loopcond ind itterations
+    COND_JUMP loopcond loop
+    JUMP finished
+
+    START
+        LT loopcond ind itterations
+        COND_JUMP loopcond loop
+        BLOCK finished
+";
+
+const PRINT:&str = "
+    NUM i 0
+    NUM inc 1
+    NUM itterations 1000
+    STR hello \"Hello!\\n\"
+    STR name \"My name is William.\\n\"
+
+    START
+        BLOCK loop
+        STDOUT hello
+        STDOUT name
+        ADD i i inc
+        LT again i itterations
+        COND_JUMP again loop
+";
+
+const RUNS:u32 = 20;
+
+fn time(name:&str, src:&str) {
+    let program = Parser::new(String::from(src)).assemble().unwrap();
+    let mut total = Duration::ZERO;
+    for _ in 0..RUNS {
+        let mut exec = Executor::new(program.bytecode.clone());
+        let now = Instant::now();
+        exec.run().unwrap();
+        total += now.elapsed();
+    }
+    eprintln!("{:>6}: {:.3?} per run", name, total / RUNS);
+}
+
+fn main() {
+    time("loop", LOOP);
+    time("print", PRINT);
+}
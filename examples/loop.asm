@@ -0,0 +1,22 @@
+NUM ind 0
+NUM sum 0
+NUM inc 1
+NUM adder -1.289893
+NUM itterations 1000
+STR nl "\n"
+
+BLOCK loop
+    MUL additive ind adder
+    ADD sum sum additive
+
+ADD ind ind inc
+LT loopcond ind itterations
+COND_JUMP loopcond loop
+JUMP finished
+
+START
+    LT loopcond ind itterations
+    COND_JUMP loopcond loop
+    BLOCK finished
+    STDOUT sum
+    STDOUT nl
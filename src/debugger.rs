@@ -0,0 +1,231 @@
+use std::collections::HashMap;
+use crate::lexer::asm::Program;
+use crate::lexer::disasm::Disassembler;
+use crate::repl::{Interactive, Reply};
+use crate::vm::debug::{Breakpoint, Pause};
+use crate::vm::error::VmError;
+use crate::vm::io::Io;
+use crate::vm::vm::Executor;
+
+const HELP:&str = "\
+    step [n], s      run the next n instructions, 1 by default
+    continue, c      run until a breakpoint or the end
+    break [at], b    stop at <line>, @<offset> or the block <name>; list breakpoints without <at>
+    delete <at>, d   remove a breakpoint
+    print <name>, p  show a variable
+    frames, f        show every scope and its variables
+    where, w         show the next instruction
+    quit, q          leave
+";
+
+/// A text-mode debugger pausing a program before each instruction it is asked to.
+pub struct Debugger {
+    exec:Executor,
+    symbols:HashMap<String, u32>
+}
+
+impl Debugger {
+    /// Debugs `program`, which should have passed `verify`.
+    pub fn new(program:Program) -> Debugger {
+        return Debugger {
+            exec: Executor::new(program.bytecode).with_debug_info(program.debug_info),
+            symbols: program.symbols
+        };
+    }
+
+    /// Runs STDOUT and STDIN against `io` instead of the process's standard streams.
+    pub fn with_io(mut self, io:Box<dyn Io>) -> Debugger {
+        self.exec = self.exec.with_io(io);
+        return self;
+    }
+
+    /// Describes where the program is paused.
+    fn position(&self) -> String {
+        let Some(offset) = self.exec.position() else {
+            return String::from("not running\n");
+        };
+        let instruction = Disassembler::new(self.exec.bytecode()).with_symbols(&self.symbols).instruction(offset)
+            .unwrap_or_else(|err| err);
+        return match self.exec.line() {
+            Some(line) => format!("line {} (offset {}): {}\n", line, offset, instruction.trim()),
+            None => format!("offset {}: {}\n", offset, instruction.trim()),
+        };
+    }
+
+    fn paused(&self, result:Result<Pause, VmError>) -> Reply {
+        return match result {
+            Ok(Pause::Finished) => Reply::Text(String::from("finished\n")),
+            Ok(Pause::Breakpoint) => Reply::Text(format!("breakpoint at {}", self.position())),
+            Ok(Pause::Step) => Reply::Text(self.position()),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+    }
+
+    /// Reads `<line>`, `@<offset>` or a block name.
+    fn breakpoint(&self, at:&str) -> Result<Breakpoint, String> {
+        if let Some(offset) = at.strip_prefix('@') {
+            return offset.parse().map(Breakpoint::Offset).map_err(|_| format!("invalid offset `{}`", offset));
+        }
+        if let Ok(line) = at.parse() {
+            return Ok(Breakpoint::Line(line));
+        }
+        return match self.symbols.get(at) {
+            Some(id) => Ok(Breakpoint::Block(*id)),
+            None => Err(format!("unknown name `{}`", at)),
+        };
+    }
+
+    /// A name for `id`, or `%id` as the disassembler writes ids without one.
+    fn name(&self, id:u32) -> String {
+        let mut names:Vec<&String> = self.symbols.iter().filter(|(_, symbol)| **symbol == id).map(|(name, _)| name).collect();
+        names.sort();
+        return names.first().map_or_else(|| format!("%{}", id), |name| name.to_string());
+    }
+
+    fn frames(&self) -> String {
+        let mut out = String::new();
+        for frame in self.exec.frames() {
+            out += &match frame.function {
+                Some(function) => format!("frame {} in {}\n", frame.depth, self.name(function)),
+                None => format!("frame {}\n", frame.depth),
+            };
+            for (id, value) in frame.variables {
+                out += &format!("    {} = {}\n", self.name(id), value);
+            }
+        }
+        return out;
+    }
+}
+
+impl Interactive for Debugger {
+    fn prompt(&self) -> &'static str {
+        return "(debug) ";
+    }
+
+    fn eval(&mut self, line:&str) -> Reply {
+        let mut words = line.split_whitespace();
+        let command = words.next().unwrap_or("");
+        let argument = words.next();
+        if words.next().is_some() {
+            return Reply::Error(format!("`{}` takes at most one argument", command));
+        }
+        return match (command, argument) {
+            ("", None) => Reply::Text(String::new()),
+            ("step" | "s", count) => {
+                let Ok(count) = count.map_or(Ok(1), str::parse::<usize>) else {
+                    return Reply::Error(String::from("step takes a number of instructions"));
+                };
+                let mut result = Ok(Pause::Step);
+                for _ in 0..count {
+                    result = self.exec.step();
+                    if !matches!(result, Ok(Pause::Step)) {
+                        break;
+                    }
+                }
+                self.paused(result)
+            },
+            ("continue" | "c", None) => {
+                let result = self.exec.run_until_breakpoint();
+                self.paused(result)
+            },
+            ("break" | "b", None) => {
+                Reply::Text(self.exec.breakpoints().iter().map(|breakpoint| format!("{}\n", breakpoint)).collect())
+            },
+            ("break" | "b", Some(at)) => match self.breakpoint(at) {
+                Ok(breakpoint) => {
+                    self.exec.add_breakpoint(breakpoint);
+                    Reply::Text(String::new())
+                },
+                Err(err) => Reply::Error(err),
+            },
+            ("delete" | "d", Some(at)) => match self.breakpoint(at) {
+                Ok(breakpoint) if self.exec.remove_breakpoint(breakpoint) => Reply::Text(String::new()),
+                Ok(breakpoint) => Reply::Error(format!("no breakpoint at {}", breakpoint)),
+                Err(err) => Reply::Error(err),
+            },
+            ("print" | "p", Some(name)) => match self.symbols.get(name).and_then(|id| self.exec.variable(*id)) {
+                Some(value) => Reply::Text(format!("{} = {}\n", name, value)),
+                None => Reply::Error(format!("`{}` has no value", name)),
+            },
+            ("frames" | "f", None) => Reply::Text(self.frames()),
+            ("where" | "w", None) => Reply::Text(self.position()),
+            ("help" | "h", None) => Reply::Text(String::from(HELP)),
+            ("quit" | "q", None) => Reply::Quit,
+            _ => Reply::Error(format!("unknown command `{}`, see help", line.trim())),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger::Debugger;
+    use crate::lexer::asm::Parser;
+    use crate::repl::{Interactive, Reply};
+    use crate::vm::io::BufferIo;
+
+    #[test]
+    fn debugger_test_session() {
+        let program = Parser::new(String::from("INT i 0
+INT one 1
+INT three 3
+START
+BLOCK loop
+    ADD i i one
+    LT again i three
+    COND_JUMP again loop
+STDOUT i
+")).assemble().unwrap();
+        let io = BufferIo::new();
+        let mut debugger = Debugger::new(program).with_io(Box::new(io.clone()));
+        let mut transcript = String::new();
+        for line in ["where", "step 2", "b loop", "b 9", "b @99", "b", "c", "p i", "c", "c", "d @99", "d 3", "c", "frames", "c", "p nope", "jump", "q"] {
+            let reply = match debugger.eval(line) {
+                Reply::Text(text) => text,
+                Reply::Error(err) => format!("error: {}\n", err),
+                Reply::Quit => String::from("quit\n"),
+            };
+            transcript += &format!("{}\n{}", line, reply);
+        }
+        assert_eq!(transcript, "\
+where
+not running
+step 2
+line 3 (offset 8): INT three 3
+b loop
+b 9
+b @99
+b
+block 87
+line 9
+offset 99
+c
+breakpoint at line 6 (offset 16): ADD i i one
+p i
+i = 0
+c
+breakpoint at line 6 (offset 16): ADD i i one
+c
+breakpoint at line 6 (offset 16): ADD i i one
+d @99
+d 3
+error: no breakpoint at line 3
+c
+breakpoint at line 9 (offset 30): STDOUT i
+frames
+frame 1
+    i = 3
+    one = 1
+    three = 3
+    again = false
+c
+finished
+p nope
+error: `nope` has no value
+jump
+error: unknown command `jump`, see help
+q
+quit
+");
+        assert_eq!(io.output(), "3");
+    }
+}
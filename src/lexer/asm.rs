@@ -1,11 +1,74 @@
 use std::str::Chars;
-use crate::vm::{vm::Executor, bytecodes::BytecodeBuilder};
+use std::ops::RangeInclusive;
+use std::fmt;
+use crate::vm::{vm::Executor, bytecodes::{BytecodeBuilder, ByteCode, __MAX_INSTR_INT__}, debug::DebugInfo, error::VmError, io::{Io, StdIo}, opt::optimize, verify::{verify, Problem}};
+use crate::lexer::diagnostics::{self, Diagnostic};
 use std::collections::HashMap;
+
+/// The output of `Parser::assemble`.
+#[derive(Debug)]
+pub struct Program {
+    pub bytecode:Box<ByteCode>,
+    /// Maps every name in the source to the id it was assembled to.
+    pub symbols:HashMap<String, u32>,
+    /// The source line of every instruction.
+    pub debug_info:DebugInfo
+}
+
+/// Why `Parser::run` failed.
+#[derive(Debug)]
+pub enum RunError {
+    Assembly(Vec<Diagnostic>),
+    Verification(Vec<Problem>),
+    Runtime(VmError)
+}
+
+impl fmt::Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            RunError::Assembly(errors) => write!(f, "{}", diagnostics::render(errors)),
+            RunError::Verification(problems) => {
+                write!(f, "{}", problems.iter().map(|problem| problem.to_string()).collect::<Vec<String>>().join("\n"))
+            },
+            RunError::Runtime(err) => write!(f, "{}", err),
+        };
+    }
+}
+
+impl From<VmError> for RunError {
+    fn from(err: VmError) -> Self {
+        RunError::Runtime(err)
+    }
+}
+
+/// A word of an instruction along with where it was written.
+#[derive(Clone, Debug)]
+struct Token {
+    /// The token's value, with quotes and escapes resolved for strings.
+    text:String,
+    /// The token exactly as written in the source.
+    raw:String,
+    line:usize,
+    column:usize
+}
+
+impl Token {
+    fn quoted(&self) -> bool {
+        return self.raw.starts_with('"');
+    }
+}
+
 pub struct Parser {
     /// This is the raw source code
     src:String,
     /// This stores the variables
-    vars:HashMap<String, u32>
+    vars:HashMap<String, u32>,
+    /// Errors found by the current `assemble` call
+    diagnostics:Vec<Diagnostic>,
+    /// The level `run` passes to `optimize`
+    opt_level:u8,
+    /// Handed to the executor by `run`
+    io:Box<dyn Io>
 }
 
 macro_rules! escape_character {
@@ -22,56 +85,127 @@ macro_rules! escape_character {
 }
 
 macro_rules! binary_emit {
-    ($self:ident, $emit:ident, $instr_prts:ident, $bb:ident) => {
+    ($self:ident, $emit:ident, $tokens:ident, $bb:ident) => {
         {
-            if $self.vars.contains_key(&$instr_prts[1]) {
-                $bb.$emit($self.vars[&$instr_prts[2]], $self.vars[&$instr_prts[3]], Some($self.vars[&$instr_prts[1]]));
-            } else {
-                $self.vars.insert($instr_prts[1].clone(), $bb.$emit($self.vars[&$instr_prts[2]], $self.vars[&$instr_prts[3]], None));
+            let lhs = $self.lookup(&$tokens[2]);
+            let rhs = $self.lookup(&$tokens[3]);
+            if let (Some(cid), Some(lhs), Some(rhs)) = ($self.target(&$tokens[1]), lhs, rhs) {
+                let cid = $bb.$emit(lhs, rhs, cid);
+                $self.vars.insert($tokens[1].text.clone(), cid);
+            }
+        }
+    };
+}
+
+macro_rules! unary_emit {
+    ($self:ident, $emit:ident, $tokens:ident, $bb:ident) => {
+        {
+            let item = $self.lookup(&$tokens[2]);
+            if let (Some(cid), Some(item)) = ($self.target(&$tokens[1]), item) {
+                let cid = $bb.$emit(item, cid);
+                $self.vars.insert($tokens[1].text.clone(), cid);
             }
         }
     };
 }
 
+/// How many operands each mnemonic takes.
+fn arity(mnemonic:&str) -> Option<RangeInclusive<usize>> {
+    return Some(match mnemonic {
+        "START" | "BEGIN_SCOPE" | "END_SCOPE" | "END" => 0..=0,
+        "RET" => 0..=1,
+        "ALLOCA" | "DEL" | "STDOUT" | "STDIN" | "BLOCK" | "JUMP" | "CLOSE" => 1..=1,
+        "STR" | "NUM" | "INT" | "BOOL" | "COND_JUMP" | "STORE" | "CAST_NUM" | "CAST_INT" | "CAST_FLOAT" | "CAST_STR"
+        | "READ" | "WRITE" | "PUSH" | "POP" | "NOT"
+        | "NEG" | "ABS" | "FLOOR" | "CEIL" | "ROUND" | "SQRT" | "SIN" | "COS" | "LOG" => 2..=2,
+        "ADD" | "SUB" | "MUL" | "DIV" | "MOD" | "EXP"
+        | "EQ" | "NEQ" | "GT" | "LT" | "GTE" | "LTE" | "FMT_NUM" | "OPEN"
+        | "AND" | "OR" | "BAND" | "BOR" | "BXOR" | "SHL" | "SHR" | "MIN" | "MAX" => 3..=3,
+        // FMT out format arg1 arg2 ...
+        "FMT" => 2..=usize::MAX,
+        // LIST out item1 item2 ...
+        "LIST" => 1..=usize::MAX,
+        // INDEX out list index1 index2 ... and STORE_INDEX list item index1 index2 ...
+        "INDEX" | "STORE_INDEX" => 3..=usize::MAX,
+        // FUNC name param1 param2 ...
+        "FUNC" => 1..=usize::MAX,
+        // CALL out name arg1 arg2 ...
+        "CALL" => 2..=usize::MAX,
+        _ => return None
+    });
+}
+
 impl Parser {
     pub fn new(src:String) -> Parser {
-        return Parser { src:src, vars:HashMap::new()};
+        return Parser { src:src, vars:HashMap::new(), diagnostics:vec![], opt_level:0, io:Box::new(StdIo) };
+    }
+
+    /// Runs programs against `io` instead of the process's standard streams. Every `run` uses the
+    /// same `io`.
+    pub fn with_io(mut self, io:Box<dyn Io>) -> Parser {
+        self.io = io;
+        return self;
     }
-    /// Converts self.src into an itterable of strings.
-    fn parse_instr(&mut self, instr:String) -> Vec<String> {
+
+    /// Optimises the program at `level` before `run` executes it, see `optimize`.
+    pub fn with_optimization(mut self, level:u8) -> Parser {
+        self.opt_level = level;
+        return self;
+    }
+
+    /// Splits one line of source into tokens.
+    fn parse_instr(&mut self, instr:&str, line:usize) -> Vec<Token> {
         let mut chars = instr.chars();
-        let mut ret_inst:Vec<String> = vec![];
-        let mut str_buff = String::new();
-        while let Some(c) = chars.next() {
+        let mut ret_inst:Vec<Token> = vec![];
+        loop {
+            let start = instr.len() - chars.as_str().len();
+            let Some(c) = chars.next() else {
+                break
+            };
             match c {
                 '"' => {
                     //string
-                    ret_inst.push(self.parse_str(&mut chars));
-                }
-                ' ' | '\t' | '\r' | '\n' => {
-                    //num
-                    ret_inst.push(str_buff.clone());
-                    str_buff.clear();
+                    let text = self.parse_str(&mut chars);
+                    let end = instr.len() - chars.as_str().len();
+                    ret_inst.push(Token {
+                        text: text,
+                        raw: instr[start..end].to_string(),
+                        line: line,
+                        column: instr[..start].chars().count() + 1
+                    });
                 }
+                ' ' | '\t' | '\r' | '\n' => {}
                 _ => {
                     //label
-                    str_buff.push(c);
+                    while let Some(next) = chars.clone().next() {
+                        if next.is_whitespace() || next == '"' {
+                            break
+                        }
+                        chars.next();
+                    }
+                    let end = instr.len() - chars.as_str().len();
+                    ret_inst.push(Token {
+                        text: instr[start..end].to_string(),
+                        raw: instr[start..end].to_string(),
+                        line: line,
+                        column: instr[..start].chars().count() + 1
+                    });
                 }
             };
         }
-        if str_buff != "" {
-            ret_inst.push(str_buff);
-        }
         return ret_inst
     }
 
     fn parse_str(&mut self, instr:&mut Chars) -> String {
         let mut escape = false;
         let mut string = String::new();
-        while let Some(c) = instr.next() {
+        for c in instr.by_ref() {
             match c {
                 '\\' => {
-                    escape = true;
+                    if escape {
+                        string.push('\\');
+                    }
+                    escape = !escape;
                 },
                 '"' => {
                     if escape {
@@ -93,93 +227,404 @@ impl Parser {
         return string;
     }
 
-    pub fn run(&mut self) {
-        let mut src = self.src.clone();
+    fn error(&mut self, token:&Token, message:String) {
+        self.diagnostics.push(Diagnostic::new(&self.src, token.line, token.column, &token.raw, message));
+    }
+
+    /// Resolves a name that must already be defined.
+    fn lookup(&mut self, token:&Token) -> Option<u32> {
+        if token.quoted() {
+            self.error(token, String::from("expected a name but found a string literal"));
+            return None;
+        }
+        if let Some(id) = self.vars.get(&token.text) {
+            return Some(*id);
+        }
+        self.error(token, format!("undefined name `{}`", token.text));
+        return None;
+    }
+
+    /// Resolves the name an instruction writes to, which is `Some(None)` if it is new.
+    fn target(&mut self, token:&Token) -> Option<Option<u32>> {
+        if token.quoted() {
+            self.error(token, String::from("expected a name but found a string literal"));
+            return None;
+        }
+        return Some(self.vars.get(&token.text).copied());
+    }
+
+    /// Names written as `%id` refer to that exact id, which is how the disassembler prints
+    /// ids it has no symbol for.
+    fn claim_raw_ids(&mut self, tokens:&[Token], bb:&mut BytecodeBuilder) {
+        for token in tokens.iter().skip(1) {
+            if token.quoted() {
+                continue
+            }
+            if let Some(Ok(id)) = token.text.strip_prefix('%').map(str::parse::<u32>) {
+                if !self.vars.contains_key(&token.text) {
+                    if id <= __MAX_INSTR_INT__ {
+                        self.error(token, format!("id {} is reserved for opcodes", id));
+                    } else if bb.reserve_id(id).is_err() {
+                        self.error(token, format!("id {} is already in use", id));
+                    }
+                    self.vars.insert(token.text.clone(), id);
+                }
+            }
+        }
+    }
+
+    /// Assembles, verifies, optimises if asked to and immediately executes the source.
+    pub fn run(&mut self) -> Result<(), RunError> {
+        let program = self.assemble().map_err(RunError::Assembly)?;
+        let problems = verify(&program.bytecode);
+        if !problems.is_empty() {
+            return Err(RunError::Verification(problems));
+        }
+        let bytecode = match self.opt_level {
+            0 => program.bytecode,
+            level => Box::new(optimize(&program.bytecode, level)?),
+        };
+        let io = std::mem::replace(&mut self.io, Box::new(StdIo));
+        let mut exec = Executor::new(bytecode).with_io(io);
+        let result = exec.run();
+        self.io = exec.into_io();
+        result?;
+        return Ok(());
+    }
+
+    /// Assembles the source into bytecode without running it, collecting every error found.
+    pub fn assemble(&mut self) -> Result<Program, Vec<Diagnostic>> {
+        return self.assemble_with(BytecodeBuilder::new());
+    }
+
+    /// Assembles `src` into code to append to `before`, a program this parser assembled earlier:
+    /// the names defined there can be used and new ids never clash with its ids, see
+    /// `Executor::run_appended`. If `src` has errors no names are added.
+    pub fn assemble_more(&mut self, src:&str, before:&ByteCode) -> Result<Program, Vec<Diagnostic>> {
+        self.src = String::from(src);
+        let vars = self.vars.clone();
+        let result = self.assemble_with(BytecodeBuilder::continuing(before));
+        if result.is_err() {
+            self.vars = vars;
+        }
+        return result;
+    }
+
+    fn assemble_with(&mut self, mut bb:BytecodeBuilder) -> Result<Program, Vec<Diagnostic>> {
+        self.diagnostics.clear();
+        let src = self.src.clone();
         let instructions = src.lines();
-        let mut bb = BytecodeBuilder::new();
-        let mut jumps:HashMap<usize, String> = HashMap::new();
-        for raw_instr in instructions {
-            let instr = raw_instr.trim().to_string();
-            if instr == "" || instr.starts_with("#") {
+        // Position of the id to patch, the name it refers to and what kind of name that is.
+        let mut jumps:Vec<(usize, Token, &str)> = vec![];
+        let mut open_function:Option<Token> = None;
+        let mut debug_info = DebugInfo::new();
+        for (line, raw_instr) in instructions.enumerate() {
+            let instr = raw_instr.trim();
+            if instr.is_empty() || instr.starts_with("#") {
                 continue
             }
-            let instr_prts = self.parse_instr(instr);
-            match instr_prts[0].as_str() {
+            let tokens = self.parse_instr(raw_instr, line + 1);
+            let Some(expected) = arity(&tokens[0].text) else {
+                self.error(&tokens[0], format!("unknown instruction `{}`", tokens[0].text));
+                continue
+            };
+            let found = tokens.len() - 1;
+            if !expected.contains(&found) {
+                let count = if expected.start() == expected.end() {
+                    expected.start().to_string()
+                } else if *expected.end() == usize::MAX {
+                    format!("at least {}", expected.start())
+                } else {
+                    format!("{} to {}", expected.start(), expected.end())
+                };
+                let message = format!(
+                    "{} expects {} operand{}, found {}",
+                    tokens[0].text, count, if *expected.start() == 1 && *expected.end() == 1 {""} else {"s"}, found
+                );
+                let at = if found > *expected.end() {tokens[expected.end() + 1].clone()} else {tokens[0].clone()};
+                self.error(&at, message);
+                continue
+            }
+            self.claim_raw_ids(&tokens, &mut bb);
+            let offset = bb.src.len();
+            match tokens[0].text.as_str() {
                 "START" => {
                     bb.write_start();
                 }
                 "ALLOCA" => {
-                    self.vars.insert(instr_prts[1].clone(), bb.write_alloca(None));
+                    if let Some(cid) = self.target(&tokens[1]) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_alloca(cid));
+                    }
                 },
                 "STR" => {
-                    if self.vars.contains_key(&instr_prts[1]) {
-                        bb.write_str(instr_prts[2].clone(), Some(self.vars[&instr_prts[1]]));
-                    } else {
-                        self.vars.insert(instr_prts[1].clone(), bb.write_str(instr_prts[2].clone(), None));
+                    if let Some(cid) = self.target(&tokens[1]) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_str(tokens[2].text.clone(), cid));
                     }
                 },
                 "NUM" => {
-                    if self.vars.contains_key(&instr_prts[1]) {
-                        bb.write_num(instr_prts[2].clone().parse::<f32>().unwrap(), Some(self.vars[&instr_prts[1]]));
-                    } else {
-                        self.vars.insert(instr_prts[1].clone(), bb.write_num(instr_prts[2].clone().parse::<f32>().unwrap(), None));
+                    let value = match tokens[2].text.parse::<f64>() {
+                        Ok(value) if !tokens[2].quoted() => Some(value),
+                        _ => {
+                            self.error(&tokens[2], format!("invalid number `{}`", tokens[2].raw));
+                            None
+                        }
+                    };
+                    if let (Some(cid), Some(value)) = (self.target(&tokens[1]), value) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_num(value, cid));
+                    }
+                },
+                "INT" => {
+                    let value = match tokens[2].text.parse::<i64>() {
+                        Ok(value) if !tokens[2].quoted() => Some(value),
+                        _ => {
+                            self.error(&tokens[2], format!("invalid integer `{}`", tokens[2].raw));
+                            None
+                        }
+                    };
+                    if let (Some(cid), Some(value)) = (self.target(&tokens[1]), value) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_int(value, cid));
                     }
                 },
                 "BOOL" => {
-                    let value = if instr_prts[2]=="true" {true} else if instr_prts[2]=="false" {false} else {panic!("BOOL value must be either `true` or `false`.")};
-                    if self.vars.contains_key(&instr_prts[1]) {
-                        bb.write_bool(value, Some(self.vars[&instr_prts[1]]));
-                    } else {
-                        self.vars.insert(instr_prts[1].clone(), bb.write_bool(value, None));
-                    }
-                },
-                "ADD" => binary_emit!(self, write_add, instr_prts, bb),
-                "SUB" => binary_emit!(self, write_sub, instr_prts, bb),
-                "MUL" => binary_emit!(self, write_mul, instr_prts, bb),
-                "DIV" => binary_emit!(self, write_div, instr_prts, bb),
-                "MOD" => binary_emit!(self, write_mod, instr_prts, bb),
-                "EXP" => binary_emit!(self, write_exp, instr_prts, bb),
-                "EQ" => binary_emit!(self, write_eq, instr_prts, bb),
-                "NEQ" => binary_emit!(self, write_neq, instr_prts, bb),
-                "GT" => binary_emit!(self, write_gt, instr_prts, bb),
-                "LT" => binary_emit!(self, write_lt, instr_prts, bb),
-                "GTE" => binary_emit!(self, write_gte, instr_prts, bb),
-                "LTE" => binary_emit!(self, write_lte, instr_prts, bb),
+                    let value = match tokens[2].raw.as_str() {
+                        "true" => Some(true),
+                        "false" => Some(false),
+                        _ => {
+                            self.error(&tokens[2], String::from("BOOL value must be either `true` or `false`"));
+                            None
+                        }
+                    };
+                    if let (Some(cid), Some(value)) = (self.target(&tokens[1]), value) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_bool(value, cid));
+                    }
+                },
+                "ADD" => binary_emit!(self, write_add, tokens, bb),
+                "SUB" => binary_emit!(self, write_sub, tokens, bb),
+                "MUL" => binary_emit!(self, write_mul, tokens, bb),
+                "DIV" => binary_emit!(self, write_div, tokens, bb),
+                "MOD" => binary_emit!(self, write_mod, tokens, bb),
+                "EXP" => binary_emit!(self, write_exp, tokens, bb),
+                "EQ" => binary_emit!(self, write_eq, tokens, bb),
+                "NEQ" => binary_emit!(self, write_neq, tokens, bb),
+                "GT" => binary_emit!(self, write_gt, tokens, bb),
+                "LT" => binary_emit!(self, write_lt, tokens, bb),
+                "GTE" => binary_emit!(self, write_gte, tokens, bb),
+                "LTE" => binary_emit!(self, write_lte, tokens, bb),
+                "AND" => binary_emit!(self, write_and, tokens, bb),
+                "OR" => binary_emit!(self, write_or, tokens, bb),
+                "NOT" => unary_emit!(self, write_not, tokens, bb),
+                "BAND" => binary_emit!(self, write_band, tokens, bb),
+                "BOR" => binary_emit!(self, write_bor, tokens, bb),
+                "BXOR" => binary_emit!(self, write_bxor, tokens, bb),
+                "SHL" => binary_emit!(self, write_shl, tokens, bb),
+                "SHR" => binary_emit!(self, write_shr, tokens, bb),
+                "NEG" => unary_emit!(self, write_neg, tokens, bb),
+                "ABS" => unary_emit!(self, write_abs, tokens, bb),
+                "FLOOR" => unary_emit!(self, write_floor, tokens, bb),
+                "CEIL" => unary_emit!(self, write_ceil, tokens, bb),
+                "ROUND" => unary_emit!(self, write_round, tokens, bb),
+                "SQRT" => unary_emit!(self, write_sqrt, tokens, bb),
+                "SIN" => unary_emit!(self, write_sin, tokens, bb),
+                "COS" => unary_emit!(self, write_cos, tokens, bb),
+                "LOG" => unary_emit!(self, write_log, tokens, bb),
+                "MIN" => binary_emit!(self, write_min, tokens, bb),
+                "MAX" => binary_emit!(self, write_max, tokens, bb),
+                "FMT_NUM" => binary_emit!(self, write_fmt_num, tokens, bb),
                 "STDOUT" => {
-                    bb.write_stdout(self.vars[&instr_prts[1]]);
+                    if let Some(out) = self.lookup(&tokens[1]) {
+                        bb.write_stdout(out);
+                    }
+                },
+                "STDIN" => {
+                    if let Some(cid) = self.target(&tokens[1]) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_stdin(cid));
+                    }
+                },
+                "STORE" => {
+                    let id = self.lookup(&tokens[1]);
+                    if let (Some(id), Some(value)) = (id, self.lookup(&tokens[2])) {
+                        bb.write_store(id, value);
+                    }
+                },
+                "DEL" => {
+                    if let Some(id) = self.lookup(&tokens[1]) {
+                        bb.write_del(id);
+                    }
+                },
+                "CAST_NUM" => {
+                    let item = self.lookup(&tokens[2]);
+                    if let (Some(cid), Some(item)) = (self.target(&tokens[1]), item) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_cast_num(item, cid));
+                    }
+                },
+                "CAST_INT" => {
+                    let item = self.lookup(&tokens[2]);
+                    if let (Some(cid), Some(item)) = (self.target(&tokens[1]), item) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_cast_int(item, cid));
+                    }
+                },
+                "CAST_FLOAT" => {
+                    let item = self.lookup(&tokens[2]);
+                    if let (Some(cid), Some(item)) = (self.target(&tokens[1]), item) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_cast_float(item, cid));
+                    }
+                },
+                "CAST_STR" => {
+                    let item = self.lookup(&tokens[2]);
+                    if let (Some(cid), Some(item)) = (self.target(&tokens[1]), item) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_cast_str(item, cid));
+                    }
+                },
+                "FMT" => {
+                    let string = self.lookup(&tokens[2]);
+                    let items:Option<Vec<u32>> = tokens[3..].iter().map(|token| self.lookup(token)).collect::<Vec<_>>().into_iter().collect();
+                    if let (Some(cid), Some(string), Some(items)) = (self.target(&tokens[1]), string, items) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_fmt(string, items, cid));
+                    }
+                },
+                "OPEN" => binary_emit!(self, write_open, tokens, bb),
+                "CLOSE" => {
+                    if let Some(file) = self.lookup(&tokens[1]) {
+                        bb.write_close(file);
+                    }
+                },
+                "READ" => {
+                    let file = self.lookup(&tokens[2]);
+                    if let (Some(cid), Some(file)) = (self.target(&tokens[1]), file) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_read(file, cid));
+                    }
+                },
+                "WRITE" => {
+                    let file = self.lookup(&tokens[1]);
+                    if let (Some(file), Some(value)) = (file, self.lookup(&tokens[2])) {
+                        bb.write_write(file, value);
+                    }
+                },
+                "LIST" => {
+                    let items:Option<Vec<u32>> = tokens[2..].iter().map(|token| self.lookup(token)).collect::<Vec<_>>().into_iter().collect();
+                    if let (Some(cid), Some(items)) = (self.target(&tokens[1]), items) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_list(items, cid));
+                    }
+                },
+                "INDEX" => {
+                    let list = self.lookup(&tokens[2]);
+                    let indices:Option<Vec<u32>> = tokens[3..].iter().map(|token| self.lookup(token)).collect::<Vec<_>>().into_iter().collect();
+                    if let (Some(cid), Some(list), Some(indices)) = (self.target(&tokens[1]), list, indices) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_index(list, indices, cid));
+                    }
+                },
+                "STORE_INDEX" => {
+                    let ids:Option<Vec<u32>> = tokens[1..].iter().map(|token| self.lookup(token)).collect::<Vec<_>>().into_iter().collect();
+                    if let Some(ids) = ids {
+                        bb.write_store_index(ids[0], ids[1], ids[2..].to_vec());
+                    }
+                },
+                "PUSH" => {
+                    let list = self.lookup(&tokens[1]);
+                    if let (Some(list), Some(item)) = (list, self.lookup(&tokens[2])) {
+                        bb.write_push(list, item);
+                    }
+                },
+                "POP" => {
+                    let list = self.lookup(&tokens[2]);
+                    if let (Some(cid), Some(list)) = (self.target(&tokens[1]), list) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_pop(list, cid));
+                    }
+                },
+                "BEGIN_SCOPE" => {
+                    bb.write_begin_scope();
+                },
+                "END_SCOPE" => {
+                    bb.write_end_scope();
                 },
                 "BLOCK" => {
-                    if self.vars.contains_key(&instr_prts[1]) {
-                        bb.write_block(Some(self.vars[&instr_prts[1]]));
-                    } else {
-                        self.vars.insert(instr_prts[1].clone(), bb.write_block(None));
+                    if let Some(cid) = self.target(&tokens[1]) {
+                        self.vars.insert(tokens[1].text.clone(), bb.write_block(cid));
                     }
                 },
                 "JUMP" => {
-                    if self.vars.contains_key(&instr_prts[1]) {
-                        bb.write_jump(self.vars[&instr_prts[1]]);
-                    } else {
-                        bb.write_jump(0);
-                        jumps.insert(bb.src.len() - 1, instr_prts[1].clone());
+                    if let Some(block) = self.target(&tokens[1]) {
+                        bb.write_jump(block.unwrap_or(0));
+                        if block.is_none() {
+                            jumps.push((bb.src.len() - 1, tokens[1].clone(), "block"));
+                        }
                     }
                 },
                 "COND_JUMP" => {
-                    if self.vars.contains_key(&instr_prts[1]) {
-                        bb.write_cond_jump(self.vars[&instr_prts[2]], self.vars[&instr_prts[1]]);
-                    } else {
-                        bb.write_cond_jump(0, self.vars[&instr_prts[1]]);
-                        jumps.insert(bb.src.len() - 3, instr_prts[1].clone());
+                    let cond = self.lookup(&tokens[1]);
+                    if let (Some(cond), Some(block)) = (cond, self.target(&tokens[2])) {
+                        bb.write_cond_jump(block.unwrap_or(0), cond);
+                        if block.is_none() {
+                            jumps.push((bb.src.len() - 3, tokens[2].clone(), "block"));
+                        }
+                    }
+                },
+                "FUNC" => {
+                    if let Some(outer) = &open_function {
+                        let message = format!("FUNC cannot be nested inside `{}`", outer.text);
+                        self.error(&tokens[0], message);
+                        continue
+                    }
+                    let params:Option<Vec<Option<u32>>> = tokens[2..].iter().map(|token| self.target(token)).collect::<Vec<_>>().into_iter().collect();
+                    if let (Some(cid), Some(params)) = (self.target(&tokens[1]), params) {
+                        let (func, params) = bb.write_func(params, cid);
+                        self.vars.insert(tokens[1].text.clone(), func);
+                        for (token, param) in tokens[2..].iter().zip(params) {
+                            self.vars.insert(token.text.clone(), param);
+                        }
+                    }
+                    open_function = Some(tokens[1].clone());
+                },
+                "END" => {
+                    if open_function.take().is_none() {
+                        self.error(&tokens[0], String::from("END without a matching FUNC"));
+                    }
+                    bb.write_end();
+                },
+                "RET" => {
+                    match tokens.get(1) {
+                        Some(token) => {
+                            if let Some(value) = self.lookup(token) {
+                                bb.write_ret(Some(value));
+                            }
+                        },
+                        None => bb.write_ret(None),
+                    }
+                },
+                "CALL" => {
+                    let args:Option<Vec<u32>> = tokens[3..].iter().map(|token| self.lookup(token)).collect::<Vec<_>>().into_iter().collect();
+                    if let (Some(cid), Some(func), Some(args)) = (self.target(&tokens[1]), self.target(&tokens[2]), args) {
+                        let at = bb.src.len() + 2;
+                        self.vars.insert(tokens[1].text.clone(), bb.write_call(func.unwrap_or(0), args, cid));
+                        if func.is_none() {
+                            jumps.push((at, tokens[2].clone(), "function"));
+                        }
                     }
                 },
-                _ => {}
+                _ => unreachable!("every mnemonic with an arity is emitted")
+            }
+            if bb.src.len() > offset {
+                debug_info.add(offset, line + 1);
+            }
+        }
+        if let Some(func) = open_function {
+            self.error(&func, format!("FUNC `{}` is missing its END", func.text));
+        }
+        // Jumps and calls to names defined further down are patched once every name has an id.
+        for (key, token, kind) in jumps.iter() {
+            match self.vars.get(&token.text) {
+                Some(id) => bb.src.set(*key, *id),
+                None => self.error(token, format!("undefined {} `{}`", kind, token.text)),
             }
         }
-        // todo: Substitute the jumps so they can be called on blocks that are defered in their initialization.
-        for (key, val) in jumps.iter() {
-            bb.src.set(*key, self.vars[val]);
+        if !self.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.diagnostics));
         }
-        let mut exec = Executor::new(bb.src);
-        exec.run();
+        return Ok(Program {
+            bytecode: bb.src,
+            symbols: self.vars.clone(),
+            debug_info: debug_info
+        });
     }
 }
 
@@ -187,27 +632,434 @@ impl Parser {
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::asm::Parser;
+    use crate::lexer::asm::{Parser, RunError};
+    use crate::vm::error::{VmError, VmErrorKind};
+    use crate::vm::bytecodes as bc;
+    use crate::vm::io::BufferIo;
+    use crate::vm::opt::MAX_LEVEL;
+    use crate::vm::vm::Executor;
+    use std::path::{Path, PathBuf};
 
+    /// Runs the source, returning everything it printed.
+    fn output(src:&str) -> String {
+        let io = BufferIo::new();
+        Parser::new(String::from(src)).with_io(Box::new(io.clone())).run().unwrap();
+        return io.output();
+    }
+
+    const GOLDEN_DIR:&str = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden");
+
+    /// Runs a golden program at `level`, returning what it printed and the error it stopped with.
+    fn golden_run(src:&str, input:&str, level:u8) -> (String, Option<RunError>) {
+        let io = BufferIo::with_input(input);
+        let result = Parser::new(String::from(src)).with_io(Box::new(io.clone())).with_optimization(level).run();
+        return (io.output(), result.err());
+    }
+
+    /// Reads the file with `extension` next to a golden program, or an empty string if there is none.
+    fn golden_file(program:&Path, extension:&str) -> String {
+        return std::fs::read_to_string(program.with_extension(extension)).unwrap_or_default();
+    }
+
+    /// Every `tests/golden/*.asm` program prints its `.out` file given its `.in` file as input,
+    /// and fails with its `.err` file if there is one. Set `BLESS=1` to rewrite the expectations
+    /// from what the programs currently do.
     #[test]
-    fn asm_test_hello_world() {
+    fn asm_test_golden() {
+        let mut programs:Vec<PathBuf> = std::fs::read_dir(GOLDEN_DIR).unwrap()
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == "asm"))
+            .collect();
+        programs.sort();
+        assert!(!programs.is_empty());
+        let bless = std::env::var_os("BLESS").is_some();
+        let mut failures:Vec<String> = vec![];
+        let mut sources = String::new();
+        for program in &programs {
+            let name = program.file_stem().unwrap().to_string_lossy();
+            let src = std::fs::read_to_string(program).unwrap();
+            let input = golden_file(program, "in");
+            let (out, err) = golden_run(&src, &input, 0);
+            let err_text = err.as_ref().map(|err| format!("{}\n", err)).unwrap_or_default();
+            if bless {
+                std::fs::write(program.with_extension("out"), &out).unwrap();
+                match &err {
+                    Some(_) => std::fs::write(program.with_extension("err"), &err_text).unwrap(),
+                    None => { let _ = std::fs::remove_file(program.with_extension("err")); },
+                }
+            }
+            if out != golden_file(program, "out") {
+                failures.push(format!("{}: stdout was {:?}", name, out));
+            }
+            if err_text != golden_file(program, "err") {
+                failures.push(format!("{}: error was {:?}", name, err_text));
+            }
+            // Optimising may move instructions, so only the kind of a runtime error has to match.
+            let (optimized_out, optimized_err) = golden_run(&src, &input, MAX_LEVEL);
+            if optimized_out != out {
+                failures.push(format!("{}: stdout at -O{} was {:?}", name, MAX_LEVEL, optimized_out));
+            }
+            let same_error = match (&err, &optimized_err) {
+                (None, None) => true,
+                (Some(RunError::Runtime(err)), Some(RunError::Runtime(optimized))) => err.kind == optimized.kind,
+                (Some(err), Some(optimized)) => err.to_string() == optimized.to_string(),
+                _ => false,
+            };
+            if !same_error {
+                failures.push(format!("{}: error at -O{} was {:?}", name, MAX_LEVEL, optimized_err.map(|err| err.to_string())));
+            }
+            sources.push_str(&src);
+        }
+        assert!(failures.is_empty(), "golden programs differ:\n{}", failures.join("\n"));
+
+        let mnemonics:Vec<&str> = sources.lines().filter_map(|line| line.split_whitespace().next()).collect();
+        let untested:Vec<&str> = (bc::ENDL + 1..bc::__MAX_INSTR_INT__)
+            .filter_map(bc::mnemonic)
+            .filter(|mnemonic| !mnemonics.contains(mnemonic))
+            .collect();
+        assert!(untested.is_empty(), "no golden program uses {:?}", untested);
+    }
+
+    #[test]
+    fn asm_test_assemble_without_running() {
         let mut lex = Parser::new(String::from(
             "
+            NUM num1 7
             START
                 STR hello \"Hello world!\\n\"
                 STDOUT hello
             "
         ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        let program = lex.assemble().unwrap();
+        assert!(!program.bytecode.is_empty());
+        assert!(program.symbols.contains_key("num1"));
+        assert!(program.symbols.contains_key("hello"));
+        assert_ne!(program.symbols["num1"], program.symbols["hello"]);
+        Executor::new(program.bytecode).run().unwrap();
     }
 
     #[test]
-    fn asm_test_add() {
+    fn asm_test_undefined_name() {
+        let errors = Parser::new(String::from(
+            "START\n    NUM a 1\n    ADD b a missing\n"
+        )).assemble().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (3, 13));
+        assert_eq!(errors[0].token, "missing");
+        assert_eq!(errors[0].message, "undefined name `missing`");
+    }
+
+    #[test]
+    fn asm_test_raw_id_in_use() {
+        let a = Parser::new(String::from("START\nSTR a \"x\"\n")).assemble().unwrap().symbols["a"];
+        let src = format!("START\nSTR a \"x\"\nSTR %{} \"y\"\nSTDOUT a\n", a);
+        let errors = Parser::new(src).assemble().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (3, 5));
+        assert_eq!(errors[0].token, format!("%{}", a));
+        assert_eq!(errors[0].message, format!("id {} is already in use", a));
+
+        // Ids up to __MAX_INSTR_INT__ would read as opcodes or ENDL.
+        let errors = Parser::new(String::from("START\nSTR b \"b\"\nLIST l %10 b\n")).assemble().unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!((errors[0].line, errors[0].column), (3, 8));
+        assert_eq!(errors[0].message, "id 10 is reserved for opcodes");
+        assert!(Parser::new(format!("START\nINT %{} 1\n", bc::__MAX_INSTR_INT__ + 1)).assemble().is_ok());
+    }
+
+    #[test]
+    fn asm_test_diagnostic_snippet() {
+        let errors = Parser::new(String::from(
+            "START\n    STDOUT nowhere\n"
+        )).assemble().unwrap_err();
+        assert_eq!(errors[0].to_string(), [
+            "error: undefined name `nowhere`",
+            " --> line 2, column 12",
+            "  |",
+            "2 |     STDOUT nowhere",
+            "  |            ^^^^^^^",
+        ].join("\n"));
+    }
+
+    #[test]
+    fn asm_test_reports_every_error() {
+        let errors = Parser::new(String::from(
+            "
+            START
+                ADDD a b c
+                NUM a
+                NUM b 1 2
+                NUM c one
+                BOOL d maybe
+                STDOUT \"literal\"
+                JUMP nowhere
+                NUM ok 1
+            "
+        )).assemble().unwrap_err();
+        let found:Vec<(usize, &str, &str)> = errors.iter()
+            .map(|err| (err.line, err.token.as_str(), err.message.as_str()))
+            .collect();
+        assert_eq!(found, vec![
+            (3, "ADDD", "unknown instruction `ADDD`"),
+            (4, "NUM", "NUM expects 2 operands, found 1"),
+            (5, "2", "NUM expects 2 operands, found 3"),
+            (6, "one", "invalid number `one`"),
+            (7, "maybe", "BOOL value must be either `true` or `false`"),
+            (8, "\"literal\"", "expected a name but found a string literal"),
+            (9, "nowhere", "undefined block `nowhere`"),
+        ]);
+    }
+
+    #[test]
+    fn asm_test_cond_jump_forward() {
+        let out = output(
+            "
+            START
+                BOOL yes true
+                COND_JUMP yes skip
+                STR never \"unreachable\\n\"
+                STDOUT never
+                BLOCK skip
+            "
+        );
+        assert_eq!(out, "");
+    }
+
+    #[test]
+    fn asm_test_fmt() {
+        let out = output(
+            "
+            START
+                NUM count 3
+                NUM price 2.5
+                NUM precision 2
+                STR item \"apples\"
+                STR template \"{} {} cost {} {{total}}\\n\"
+                FMT_NUM total price precision
+                FMT msg template count item total
+                STDOUT msg
+            "
+        );
+        assert_eq!(out, "3 apples cost 2.50 {total}\n");
+    }
+
+    #[test]
+    fn asm_test_scope_store_del() {
+        let out = output(
+            "
+            START
+                NUM outer 1
+                ALLOCA slot
+                STORE slot outer
+                BEGIN_SCOPE
+                    NUM outer 2
+                    STDOUT outer
+                END_SCOPE
+                STDOUT outer
+                STDOUT slot
+                DEL slot
+                STR nl \"\\n\"
+                STDOUT nl
+            "
+        );
+        assert_eq!(out, "211\n");
+    }
+
+    #[test]
+    fn asm_test_casts() {
+        let out = output(
+            "
+            START
+                STR digits \"42\"
+                CAST_NUM num digits
+                NUM one 1
+                ADD sum num one
+                CAST_STR text sum
+                CAST_STR same text
+                STDOUT same
+                STR nl \"\\n\"
+                STDOUT nl
+            "
+        );
+        assert_eq!(out, "43\n");
+    }
+
+    #[test]
+    fn asm_test_scripted_input() {
+        let io = BufferIo::with_input("Ada\r\nLovelace");
         let mut lex = Parser::new(String::from(
+            "
+            START
+                STR template \"hello {} {}!\"
+                STDIN first
+                STDIN last
+                STDIN nothing
+                FMT msg template first last
+                STDOUT msg
+                STDOUT nothing
+            "
+        )).with_io(Box::new(io.clone()));
+        lex.run().unwrap();
+        assert_eq!(io.take_output(), "hello Ada Lovelace!");
+        // The same io is used again by the next run.
+        lex.run().unwrap();
+        assert_eq!(io.output(), "hello  !");
+    }
+
+    #[test]
+    fn asm_test_fmt_needs_a_format_string() {
+        let errors = Parser::new(String::from("START\n    FMT out\n")).assemble().unwrap_err();
+        assert_eq!(errors[0].message, "FMT expects at least 2 operands, found 1");
+    }
+
+    #[test]
+    fn asm_test_file_io() {
+        let path = std::env::temp_dir().join(format!("asm_test_file_io_{}.txt", std::process::id()));
+        let mut lex = Parser::new(format!(
+            "
+            START
+                STR path \"{}\"
+                STR write \"w\"
+                STR append \"a\"
+                STR read \"r\"
+                STR first \"first line\\n\"
+                NUM second 2
+                OPEN out path write
+                WRITE out first
+                CLOSE out
+                OPEN out path append
+                WRITE out second
+                CLOSE out
+                OPEN in path read
+                READ contents in
+                CLOSE in
+                OPEN out path write
+                WRITE out contents
+                WRITE out contents
+                CLOSE out
+            ",
+            path.display()
+        ));
+        lex.run().unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "first line\n2first line\n2");
+    }
+
+    #[test]
+    fn asm_test_lists() {
+        let mut lex = Parser::new(String::from(
+            "
+            START
+                NUM one 1
+                NUM two 2
+                NUM last -1
+                LIST row one two
+                LIST grid row
+                PUSH row one
+                POP top row
+                STORE_INDEX grid top one last
+                INDEX cell grid one
+            "
+        ));
+        let err = lex.run().unwrap_err();
+        assert!(matches!(err, RunError::Runtime(VmError { kind: VmErrorKind::IndexOutOfBounds(1, 1), .. })), "{}", err);
+
+        let mut lex = Parser::new(String::from("START
+LIST row
+INDEX cell row
+"));
+        let diagnostics = lex.assemble().unwrap_err();
+        assert_eq!(diagnostics[0].message, "INDEX expects at least 3 operands, found 2");
+    }
+
+    #[test]
+    fn asm_test_function_errors() {
+        let mut lex = Parser::new(String::from("FUNC outer\nFUNC inner\nEND\nEND\nSTART\nCALL out missing\nFUNC open a\n"));
+        let messages:Vec<String> = lex.assemble().unwrap_err().into_iter().map(|diagnostic| diagnostic.message).collect();
+        assert_eq!(messages, vec![
+            "FUNC cannot be nested inside `outer`",
+            "END without a matching FUNC",
+            "FUNC `open` is missing its END",
+            "undefined function `missing`",
+        ]);
+    }
+
+    #[test]
+    fn asm_test_verification() {
+        let mut lex = Parser::new(String::from("START\nBEGIN_SCOPE\nNUM x 1\nEND_SCOPE\nSTDOUT x\n"));
+        let err = lex.run().unwrap_err();
+        assert!(matches!(&err, RunError::Verification(problems) if problems.len() == 1), "{}", err);
+        assert!(err.to_string().starts_with("verification error in STDOUT"), "{}", err);
+    }
+
+    #[test]
+    fn asm_test_optimized_run() {
+        let mut lex = Parser::new(String::from("INT a 2\nSTART\nMUL b a a\nINT zero 0\nDIV c b zero\n")).with_optimization(2);
+        let err = lex.run().unwrap_err();
+        assert!(matches!(&err, RunError::Runtime(err) if err.kind == VmErrorKind::DivisionByZero), "{}", err);
+    }
+
+    #[test]
+    fn asm_test_int_literal() {
+        let mut lex = Parser::new(String::from("INT whole 2.5\nINT fine -3\n"));
+        let diagnostics = lex.assemble().unwrap_err();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, "invalid integer `2.5`");
+    }
+
+    #[test]
+    fn asm_test_file_errors() {
+        let missing = std::env::temp_dir().join("asm_test_file_errors_missing.txt");
+        let mut lex = Parser::new(format!(
+            "
+            START
+                STR path \"{}\"
+                STR read \"r\"
+                OPEN in path read
+            ",
+            missing.display()
+        ));
+        let err = lex.run().unwrap_err();
+        assert!(matches!(err, RunError::Runtime(VmError { kind: VmErrorKind::Io(_), .. })), "{}", err);
+
+        let mut lex = Parser::new(String::from(
+            "
+            START
+                STR path \"unused.txt\"
+                STR mode \"x\"
+                OPEN file path mode
+            "
+        ));
+        let err = lex.run().unwrap_err();
+        assert!(err.to_string().contains("unknown file mode `x`"), "{}", err);
+
+        let mut lex = Parser::new(String::from(
+            "
+            START
+                STR notafile \"text\"
+                READ contents notafile
+            "
+        ));
+        let err = lex.run().unwrap_err();
+        assert!(matches!(err, RunError::Runtime(VmError { kind: VmErrorKind::TypeMismatch(_), .. })), "{}", err);
+    }
+
+    #[test]
+    fn asm_test_hello_world() {
+        let out = output(
+            "
+            START
+                STR hello \"Hello world!\\n\"
+                STDOUT hello
+            "
+        );
+        assert_eq!(out, "Hello world!\n");
+    }
+
+    #[test]
+    fn asm_test_add() {
+        let out = output(
             "
             START
                 NUM num1 7
@@ -217,17 +1069,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "9\n");
     }
 
     #[test]
     fn asm_test_bool() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 BOOL mybool true
@@ -235,17 +1083,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "true\n");
     }
 
     #[test]
     fn asm_test_sub() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -255,17 +1099,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "5\n");
     }
 
     #[test]
     fn asm_test_mul() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -275,17 +1115,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "14\n");
     }
     
     #[test]
     fn asm_test_div() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -295,17 +1131,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "3.5\n");
     }
 
     #[test]
     fn asm_test_mod() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -315,17 +1147,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "1\n");
     }
 
     #[test]
     fn asm_test_exp() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -335,17 +1163,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "49\n");
     }
 
     #[test]
     fn asm_test_eq() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -355,17 +1179,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "false\n");
     }
 
     #[test]
     fn asm_test_neq() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -375,17 +1195,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "true\n");
     }
 
     #[test]
     fn asm_test_gt() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -395,17 +1211,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "true\n");
     }
 
     #[test]
     fn asm_test_lt() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -415,17 +1227,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "false\n");
     }
 
     #[test]
     fn asm_test_gte() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -435,17 +1243,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "true\n");
     }
 
     #[test]
     fn asm_test_lte() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             START
                 NUM num1 7
@@ -455,17 +1259,13 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "false\n");
     }
 
     #[test]
     fn asm_test_cond_jump_prerender() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             NUM ind 0
             NUM sum 0
@@ -489,17 +1289,13 @@ mod tests {
                 STDOUT sum
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "499500\n");
     }
 
     #[test]
     fn asm_test_cond_jump() {
-        let mut lex = Parser::new(String::from(
+        let out = output(
             "
             NUM ind 0
             NUM sum 0
@@ -521,11 +1317,7 @@ mod tests {
                 STR nl \"\\n\"
                 STDOUT nl
             "
-        ));
-        use std::time::Instant;
-        let now = Instant::now();
-        lex.run();
-        let elapsed = now.elapsed();
-        println!("runtime: {:.4?}", elapsed);
+        );
+        assert_eq!(out, "55\n");
     }
 }
\ No newline at end of file
@@ -0,0 +1,46 @@
+use std::fmt;
+
+/// An error found while assembling, pointing at the offending token in the source.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    /// 1 based source line.
+    pub line:usize,
+    /// 1 based character column of the token within the line.
+    pub column:usize,
+    pub token:String,
+    pub message:String,
+    source_line:String
+}
+
+impl Diagnostic {
+    pub fn new(src:&str, line:usize, column:usize, token:&str, message:String) -> Diagnostic {
+        return Diagnostic {
+            line: line,
+            column: column,
+            token: token.to_string(),
+            message: message,
+            source_line: src.lines().nth(line - 1).unwrap_or("").to_string()
+        };
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let gutter = " ".repeat(self.line.to_string().len());
+        let padding:String = self.source_line.chars()
+            .take(self.column - 1)
+            .map(|c| if c == '\t' {'\t'} else {' '})
+            .collect();
+        let carets = "^".repeat(self.token.chars().count().max(1));
+        writeln!(f, "error: {}", self.message)?;
+        writeln!(f, "{}--> line {}, column {}", gutter, self.line, self.column)?;
+        writeln!(f, "{} |", gutter)?;
+        writeln!(f, "{} | {}", self.line, self.source_line)?;
+        return write!(f, "{} | {}{}", gutter, padding, carets);
+    }
+}
+
+/// Renders a list of diagnostics separated by blank lines.
+pub fn render(diagnostics:&[Diagnostic]) -> String {
+    return diagnostics.iter().map(|diagnostic| diagnostic.to_string()).collect::<Vec<String>>().join("\n\n");
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+use crate::vm::bytecodes::{self as bc, ByteCode, Constant};
+
+/// Turns bytecode back into assembly text that `Parser` can re-assemble.
+pub struct Disassembler<'a> {
+    bytecode:&'a ByteCode,
+    /// id, symbol name
+    names:HashMap<u32, String>,
+    cursor:usize
+}
+
+impl<'a> Disassembler<'a> {
+    pub fn new(bytecode:&'a ByteCode) -> Disassembler<'a> {
+        return Disassembler { bytecode: bytecode, names: HashMap::new(), cursor: 0 };
+    }
+
+    /// Prints ids using the names from a symbol table such as `Program.symbols`.
+    /// Ids without a name are printed as `%id`.
+    pub fn with_symbols(mut self, symbols:&HashMap<String, u32>) -> Disassembler<'a> {
+        for (name, id) in symbols.iter() {
+            // Keep the output deterministic if several names share an id.
+            if self.names.get(id).is_none_or(|current| name < current) {
+                self.names.insert(*id, name.clone());
+            }
+        }
+        return self;
+    }
+
+    pub fn disassemble(&mut self) -> Result<String, String> {
+        self.cursor = 0;
+        let mut out = String::new();
+        let mut indent = "";
+        // Where to return to once a function body ends.
+        let mut outer = "";
+        while self.cursor < self.bytecode.len() {
+            let offset = self.cursor;
+            let opcode = self.next()?;
+            if opcode == bc::ENDL {
+                continue
+            }
+            let text = self.instruction(offset)?;
+            if opcode == bc::BLOCK || opcode == bc::START {
+                indent = "";
+            }
+            if opcode == bc::END {
+                indent = outer;
+            }
+            out += indent;
+            out += text.as_str();
+            out.push('\n');
+            if opcode == bc::BLOCK || opcode == bc::START {
+                indent = "    ";
+            }
+            if opcode == bc::FUNC {
+                outer = indent;
+                indent = if indent.is_empty() {"    "} else {"        "};
+            }
+        }
+        return Ok(out);
+    }
+
+    /// Renders the instruction whose opcode is at `offset`, leaving the cursor after it.
+    pub fn instruction(&mut self, offset:usize) -> Result<String, String> {
+        self.cursor = offset;
+        let opcode = self.next()?;
+        let name = bc::mnemonic(opcode)
+            .ok_or_else(|| format!("Unknown opcode {:#x} at offset {}.", opcode, offset))?;
+        let mut out = String::from(name);
+        for operand in self.operands(opcode)? {
+            out.push(' ');
+            out += operand.as_str();
+        }
+        return Ok(out);
+    }
+
+    /// Reads the operands of `opcode` in assembler order, including its trailing ENDL.
+    fn operands(&mut self, opcode:u32) -> Result<Vec<String>, String> {
+        let mut ret = vec![];
+        match opcode {
+            bc::START | bc::BEGIN_SCOPE | bc::END_SCOPE | bc::END => return Ok(ret),
+            bc::JUMP => {
+                let block = self.next()?;
+                ret.push(self.name(block));
+                return Ok(ret);
+            },
+            bc::COND_JUMP => {
+                let block = self.next()?;
+                let cond = self.next()?;
+                ret.push(self.name(cond));
+                ret.push(self.name(block));
+            },
+            bc::ALLOCA | bc::DEL | bc::BLOCK | bc::STDOUT | bc::STDIN | bc::CLOSE => {
+                let id = self.next()?;
+                ret.push(self.name(id));
+            },
+            bc::STORE | bc::CAST_NUM | bc::CAST_INT | bc::CAST_FLOAT | bc::CAST_STR | bc::READ | bc::WRITE | bc::PUSH | bc::POP | bc::NOT
+            | bc::NEG | bc::ABS | bc::FLOOR | bc::CEIL | bc::ROUND | bc::SQRT | bc::SIN | bc::COS | bc::LOG => {
+                for _ in 0..2 {
+                    let id = self.next()?;
+                    ret.push(self.name(id));
+                }
+            },
+            bc::ADD | bc::SUB | bc::MUL | bc::DIV | bc::MOD | bc::EXP
+            | bc::EQ | bc::NEQ | bc::GT | bc::LT | bc::GTE | bc::LTE | bc::FMT_NUM | bc::OPEN
+            | bc::AND | bc::OR | bc::BAND | bc::BOR | bc::BXOR | bc::SHL | bc::SHR | bc::MIN | bc::MAX => {
+                for _ in 0..3 {
+                    let id = self.next()?;
+                    ret.push(self.name(id));
+                }
+            },
+            bc::RET => {
+                if self.peek()? != bc::ENDL {
+                    let value = self.next()?;
+                    ret.push(self.name(value));
+                }
+            },
+            bc::FMT | bc::LIST | bc::INDEX | bc::STORE_INDEX | bc::FUNC | bc::CALL => {
+                let cid = self.next()?;
+                ret.push(self.name(cid));
+                while self.peek()? != bc::ENDL {
+                    let id = self.next()?;
+                    ret.push(self.name(id));
+                }
+            },
+            bc::STR | bc::NUM | bc::INT => {
+                let cid = self.next()?;
+                ret.push(self.name(cid));
+                let offset = self.cursor;
+                match self.bytecode.constant(self.next()?) {
+                    Some(Constant::Int(val)) => ret.push(val.to_string()),
+                    Some(Constant::Float(val)) => ret.push(val.to_string()),
+                    Some(Constant::Str(string)) => ret.push(escape(string)),
+                    None => return Err(format!("Missing constant at offset {}.", offset)),
+                }
+            },
+            bc::BOOL => {
+                let cid = self.next()?;
+                ret.push(self.name(cid));
+                ret.push(String::from(if self.next()? == 1 {"true"} else {"false"}));
+            },
+            _ => return Err(format!("The disassembler does not support {} yet.", bc::mnemonic(opcode).unwrap_or("this opcode"))),
+        }
+        let offset = self.cursor;
+        if self.next()? != bc::ENDL {
+            return Err(format!("Expected ENDL at offset {}.", offset));
+        }
+        return Ok(ret);
+    }
+
+    fn peek(&self) -> Result<u32, String> {
+        if self.cursor >= self.bytecode.len() {
+            return Err(String::from("Unexpected end of bytecode."));
+        }
+        return Ok(self.bytecode.at(self.cursor));
+    }
+
+    fn next(&mut self) -> Result<u32, String> {
+        let ret = self.peek()?;
+        self.cursor += 1;
+        return Ok(ret);
+    }
+
+    fn name(&self, id:u32) -> String {
+        return match self.names.get(&id) {
+            Some(name) => name.clone(),
+            None => format!("%{}", id),
+        };
+    }
+}
+
+/// Quotes a string so `Parser::parse_str` reads back the same characters.
+fn escape(string:&str) -> String {
+    let mut ret = String::from("\"");
+    for c in string.chars() {
+        match c {
+            '\\' => ret += "\\\\",
+            '"' => ret += "\\\"",
+            '\n' => ret += "\\n",
+            '\t' => ret += "\\t",
+            '\r' => ret += "\\r",
+            '\0' => ret += "\\0",
+            _ => ret.push(c),
+        }
+    }
+    ret.push('"');
+    return ret;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::lexer::disasm::Disassembler;
+    use crate::vm::bytecodes::BytecodeBuilder;
+
+    const LOOP:&str = "
+        NUM ind 0
+        NUM sum 0
+        NUM inc 1
+        NUM adder -1.289893
+        NUM itterations 1000
+        STR nl \"\\n\"
+        BOOL yes true
+
+        BLOCK loop
+            MUL additive ind adder
+            ADD sum sum additive
+
+        ADD ind ind inc
+        LT loopcond ind itterations
+        COND_JUMP loopcond loop
+        JUMP finished
+
+        FUNC twice value
+            ADD doubled value value
+            RET doubled
+        END
+
+        START
+            LT loopcond ind itterations
+            COND_JUMP loopcond loop
+            BLOCK finished
+            STR quoted \"tab\\tquote\\\"slash\\\\\"
+            STDOUT sum
+            STDOUT nl
+            NUM precision 2
+            FMT_NUM rounded sum precision
+            FMT line quoted rounded sum
+            CAST_STR text yes
+            CAST_NUM back text
+            INT count -42
+            NUM limit -inf
+            NUM missing NaN
+            CAST_INT whole adder
+            CAST_FLOAT ratio count
+            BEGIN_SCOPE
+                ALLOCA temp
+                STORE temp rounded
+                STDIN answer
+                DEL temp
+            END_SCOPE
+            STR path \"report.txt\"
+            STR mode \"a\"
+            OPEN report path mode
+            WRITE report line
+            READ contents report
+            CLOSE report
+            LIST empty
+            LIST grid empty precision
+            INDEX cell grid precision inc
+            STORE_INDEX grid cell inc
+            PUSH empty cell
+            POP top empty
+            CALL four twice precision
+            LT small count inc
+            AND both small yes
+            OR either small yes
+            NOT neither either
+            BAND masked count count
+            BOR joined count count
+            BXOR flipped count count
+            SHL shifted count count
+            SHR halved count count
+            NEG minus count
+            ABS plus minus
+            FLOOR down adder
+            CEIL up adder
+            ROUND near adder
+            SQRT root precision
+            SIN sine adder
+            COS cosine adder
+            LOG ln precision
+            MIN least count adder
+            MAX most count adder
+            CALL nothing noop
+            FUNC noop
+                RET
+            END
+    ";
+
+    #[test]
+    fn disasm_test_round_trip_with_symbols() {
+        let program = Parser::new(String::from(LOOP)).assemble().unwrap();
+        let text = Disassembler::new(&program.bytecode).with_symbols(&program.symbols).disassemble().unwrap();
+        let again = Parser::new(text.clone()).assemble().unwrap();
+        assert!(*again.bytecode == *program.bytecode, "{}", text);
+    }
+
+    #[test]
+    fn disasm_test_round_trip_synthetic_names() {
+        let program = Parser::new(String::from(LOOP)).assemble().unwrap();
+        let text = Disassembler::new(&program.bytecode).disassemble().unwrap();
+        assert!(!text.contains("loop"));
+        let again = Parser::new(text.clone()).assemble().unwrap();
+        assert!(*again.bytecode == *program.bytecode, "{}", text);
+    }
+
+    #[test]
+    fn disasm_test_output() {
+        let mut bb = BytecodeBuilder::new();
+        let a = bb.write_num(-2.5, None);
+        let s = bb.write_str(String::from("{} items\n"), None);
+        bb.write_start();
+        let f = bb.write_fmt(s, vec![a], None);
+        bb.write_stdout(f);
+        let text = Disassembler::new(&bb.src).disassemble().unwrap();
+        assert_eq!(text, format!(
+            "NUM %{a} -2.5\nSTR %{s} \"{{}} items\\n\"\nSTART\n    FMT %{f} %{s} %{a}\n    STDOUT %{f}\n",
+            a = a, s = s, f = f
+        ));
+    }
+
+    #[test]
+    fn disasm_test_colliding_ids() {
+        let mut bb = BytecodeBuilder::new();
+        let s = bb.write_str(String::from("hi"), None);
+        let n = bb.write_num(1.5, Some(s + 1));
+        let text = Disassembler::new(&bb.src).disassemble().unwrap();
+        assert_eq!(text, format!("STR %{s} \"hi\"\nNUM %{n} 1.5\n", s = s, n = n));
+    }
+
+    #[test]
+    fn disasm_test_unknown_opcode() {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        bb.src.set(0, 0x3);
+        assert!(Disassembler::new(&bb.src).disassemble().unwrap_err().contains("Unknown opcode"));
+    }
+}
@@ -1 +1,3 @@
-pub mod asm;
\ No newline at end of file
+pub mod asm;
+pub mod diagnostics;
+pub mod disasm;
\ No newline at end of file
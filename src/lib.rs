@@ -0,0 +1,5 @@
+#![allow(clippy::needless_return, clippy::unused_unit, clippy::redundant_field_names)]
+pub mod vm;
+pub mod lexer;
+pub mod repl;
+pub mod debugger;
@@ -1,38 +1,316 @@
-mod vm;
-mod lexer;
-use vm::{vm::Executor, bytecodes::BytecodeBuilder};
-
-use crate::lexer::asm::Parser;
-fn main() {
-    let mut lex = Parser::new(String::from(
-        "
-        NUM ind 0
-        NUM sum 0
-        NUM inc 1
-        NUM adder -1.289893
-        NUM itterations 1000
-        STR nl \"\\n\"
-
-        BLOCK loop
-            MUL additive ind adder
-            ADD sum sum additive
-
-        ADD ind ind inc
-        LT loopcond ind itterations
-        COND_JUMP loopcond loop
-        JUMP finished
-
-        START
-            LT loopcond ind itterations
-            COND_JUMP loopcond loop
-            BLOCK finished
-            STDOUT sum
-            STDOUT nl
-        "
-    ));
-    use std::time::Instant;
-    let now = Instant::now();
-    lex.run();
-    let elapsed = now.elapsed();
-    println!("runtime: {:.4?}", elapsed);
+#![allow(clippy::needless_return, clippy::redundant_field_names)]
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::process::ExitCode;
+use std::time::Instant;
+use interpreted_language::debugger::Debugger;
+use interpreted_language::lexer::asm::{Parser, Program, RunError};
+use interpreted_language::lexer::disasm::Disassembler;
+use interpreted_language::repl::{self, Repl};
+use interpreted_language::vm::bytecodes::{ByteCode, BC_MAGIC};
+use interpreted_language::vm::debug::DebugInfo;
+use interpreted_language::vm::opt::{optimize, MAX_LEVEL};
+use interpreted_language::vm::verify::verify;
+use interpreted_language::vm::vm::Executor;
+
+const USAGE:&str = "\
+usage: interpreted_language <command> [options] <file>
+
+commands:
+    run <file>          run an assembly (.asm) or bytecode (.bc) program
+    asm <file>          assemble a program into bytecode
+    disasm <file>       print a bytecode program as assembly
+    check <file>        assemble and verify a program without running it
+    repl [file]         enter assembly line by line, after running the assembly <file> if given
+    debug <file>        step through a program with breakpoints, see `help` once inside
+
+options:
+    -o <file>           where asm writes the bytecode, <file> with a .bc extension by default
+    -O0, -O1, -O2, -O   how hard run and asm optimise, -O meaning the most
+    --time              print how long run took to stderr
+
+A <file> of - reads the program from stdin.";
+
+/// Exit codes, besides 0 for success.
+const EXIT_RUNTIME:u8 = 1;
+const EXIT_USAGE:u8 = 2;
+/// The program did not assemble, failed verification or is not valid bytecode.
+const EXIT_INVALID:u8 = 3;
+/// A file could not be read or written.
+const EXIT_IO:u8 = 4;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Command {
+    Run,
+    Asm,
+    Disasm,
+    Check,
+    Repl,
+    Debug
+}
+
+#[derive(Debug, PartialEq)]
+struct Args {
+    command:Command,
+    /// The program to read, `-` for stdin. Only `repl` can do without one.
+    input:Option<String>,
+    output:Option<String>,
+    level:u8,
+    time:bool
+}
+
+/// Why a command failed, along with the message to print.
+#[derive(Debug)]
+enum Failure {
+    Runtime(String),
+    Usage(String),
+    Invalid(String),
+    Io(String)
+}
+
+impl Failure {
+    fn exit_code(&self) -> u8 {
+        return match self {
+            Failure::Runtime(_) => EXIT_RUNTIME,
+            Failure::Usage(_) => EXIT_USAGE,
+            Failure::Invalid(_) => EXIT_INVALID,
+            Failure::Io(_) => EXIT_IO,
+        };
+    }
+
+    fn message(&self) -> &str {
+        return match self {
+            Failure::Runtime(message) | Failure::Usage(message) | Failure::Invalid(message) | Failure::Io(message) => message,
+        };
+    }
+}
+
+impl From<RunError> for Failure {
+    fn from(err:RunError) -> Self {
+        return match err {
+            RunError::Runtime(_) => Failure::Runtime(err.to_string()),
+            _ => Failure::Invalid(err.to_string()),
+        };
+    }
+}
+
+fn parse_args(args:&[String]) -> Result<Args, Failure> {
+    let usage = |message:String| Failure::Usage(format!("{}\n\n{}", message, USAGE));
+    let mut args = args.iter();
+    let command = match args.next().map(String::as_str) {
+        Some("run") => Command::Run,
+        Some("asm") => Command::Asm,
+        Some("disasm") => Command::Disasm,
+        Some("check") => Command::Check,
+        Some("repl") => Command::Repl,
+        Some("debug") => Command::Debug,
+        Some(other) => return Err(usage(format!("unknown command `{}`", other))),
+        None => return Err(Failure::Usage(String::from(USAGE))),
+    };
+    let mut input = None;
+    let mut output = None;
+    let mut level = 0;
+    let mut time = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--time" => time = true,
+            "-o" => match args.next() {
+                Some(path) => output = Some(path.clone()),
+                None => return Err(usage(String::from("-o needs a file"))),
+            },
+            flag if flag.starts_with("-O") => {
+                let rest = &flag[2..];
+                level = match if rest.is_empty() {Ok(MAX_LEVEL)} else {rest.parse::<u8>()} {
+                    Ok(parsed) if parsed <= MAX_LEVEL => parsed,
+                    _ => return Err(usage(format!("unknown optimisation level `{}`", flag))),
+                };
+            },
+            flag if flag.starts_with('-') && flag != "-" => return Err(usage(format!("unknown option `{}`", flag))),
+            _ if input.is_some() => return Err(usage(format!("unexpected argument `{}`", arg))),
+            _ => input = Some(arg.clone()),
+        }
+    }
+    if input.is_none() && command != Command::Repl {
+        return Err(usage(String::from("missing the program to read")));
+    }
+    if output.is_some() && command != Command::Asm {
+        return Err(usage(String::from("-o only applies to asm")));
+    }
+    return Ok(Args { command: command, input: input, output: output, level: level, time: time });
+}
+
+fn read_input(input:&str) -> Result<Vec<u8>, Failure> {
+    let mut bytes = vec![];
+    let result = if input == "-" {
+        std::io::stdin().read_to_end(&mut bytes).map(|_| ())
+    } else {
+        std::fs::read(input).map(|read| bytes = read)
+    };
+    return match result {
+        Ok(()) => Ok(bytes),
+        Err(err) => Err(Failure::Io(format!("cannot read {}: {}", input, err))),
+    };
+}
+
+/// Loads either format, telling bytecode from assembly by its magic number rather than the file
+/// name so stdin works too. Only assembly has symbols and debug info.
+fn load(bytes:&[u8]) -> Result<Program, Failure> {
+    if bytes.starts_with(&BC_MAGIC) {
+        let bytecode = ByteCode::deserialize(bytes).map_err(Failure::Invalid)?;
+        return Ok(Program { bytecode: Box::new(bytecode), symbols: HashMap::new(), debug_info: DebugInfo::new() });
+    }
+    let Ok(src) = std::str::from_utf8(bytes) else {
+        return Err(Failure::Invalid(String::from("the program is neither bytecode nor utf-8 assembly")));
+    };
+    return Ok(Parser::new(String::from(src)).assemble().map_err(RunError::Assembly)?);
+}
+
+/// Verifies a loaded program and optimises it at `level`.
+fn prepare(bytecode:ByteCode, level:u8) -> Result<ByteCode, Failure> {
+    let problems = verify(&bytecode);
+    if !problems.is_empty() {
+        return Err(RunError::Verification(problems).into());
+    }
+    return match level {
+        0 => Ok(bytecode),
+        level => optimize(&bytecode, level).map_err(|err| RunError::Runtime(err).into()),
+    };
+}
+
+fn execute(args:&Args) -> Result<(), Failure> {
+    if args.command == Command::Repl {
+        return interact(args.input.as_deref());
+    }
+    let input = args.input.as_deref().unwrap_or("-");
+    let Program { bytecode, symbols, debug_info } = load(&read_input(input)?)?;
+    let bytecode = *bytecode;
+    match args.command {
+        Command::Run => {
+            let mut exec = Executor::new(Box::new(prepare(bytecode, args.level)?));
+            let now = Instant::now();
+            let result = exec.run();
+            if args.time {
+                eprintln!("runtime: {:.4?}", now.elapsed());
+            }
+            result.map_err(RunError::Runtime)?;
+        },
+        Command::Asm => {
+            let bytes = prepare(bytecode, args.level)?.serialize();
+            let path = match &args.output {
+                Some(path) => path.clone(),
+                None if input == "-" => return Err(Failure::Usage(String::from("asm needs -o when reading stdin"))),
+                None => std::path::Path::new(input).with_extension("bc").display().to_string(),
+            };
+            let written = if path == "-" {std::io::stdout().write_all(&bytes)} else {std::fs::write(&path, bytes)};
+            if let Err(err) = written {
+                return Err(Failure::Io(format!("cannot write {}: {}", path, err)));
+            }
+        },
+        Command::Disasm => {
+            let text = Disassembler::new(&bytecode).with_symbols(&symbols).disassemble().map_err(Failure::Invalid)?;
+            print!("{}", text);
+        },
+        Command::Check => {
+            prepare(bytecode, 0)?;
+        },
+        Command::Debug => {
+            let program = Program { bytecode: Box::new(prepare(bytecode, 0)?), symbols: symbols, debug_info: debug_info };
+            repl::run_stdio(&mut Debugger::new(program)).map_err(|err| Failure::Io(err.to_string()))?;
+        },
+        Command::Repl => unreachable!("handled above"),
+    }
+    return Ok(());
+}
+
+fn interact(file:Option<&str>) -> Result<(), Failure> {
+    let mut repl = open_repl(file)?;
+    return repl::run_stdio(&mut repl).map_err(|err| Failure::Io(err.to_string()));
+}
+
+/// A REPL that has run `file`, which has to be assembly since lines entered later are assembled
+/// against its symbols.
+fn open_repl(file:Option<&str>) -> Result<Repl, Failure> {
+    let mut repl = Repl::new();
+    if let Some(path) = file {
+        let bytes = read_input(path)?;
+        if bytes.starts_with(&BC_MAGIC) {
+            return Err(Failure::Invalid(format!("{} is bytecode, the REPL only loads assembly", path)));
+        }
+        let Ok(src) = std::str::from_utf8(&bytes) else {
+            return Err(Failure::Invalid(format!("{} is not utf-8 assembly", path)));
+        };
+        repl.load(src)?;
+    }
+    return Ok(repl);
+}
+
+fn main() -> ExitCode {
+    let args:Vec<String> = std::env::args().skip(1).collect();
+    return match parse_args(&args).and_then(|args| execute(&args)) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            eprintln!("{}", failure.message());
+            ExitCode::from(failure.exit_code())
+        },
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args:&str) -> Result<Args, Failure> {
+        return parse_args(&args.split_whitespace().map(String::from).collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn cli_test_args() {
+        let args = parse("run -O --time prog.asm").unwrap();
+        assert_eq!(args, Args { command: Command::Run, input: Some(String::from("prog.asm")), output: None, level: MAX_LEVEL, time: true });
+        let args = parse("asm - -O1 -o out.bc").unwrap();
+        assert_eq!(args, Args { command: Command::Asm, input: Some(String::from("-")), output: Some(String::from("out.bc")), level: 1, time: false });
+        assert_eq!(parse("check prog.asm").unwrap().command, Command::Check);
+        assert_eq!(parse("repl").unwrap().input, None);
+
+        for bad in ["", "build prog.asm", "run", "run a.asm b.asm", "run -O9 a.asm", "run --fast a.asm", "disasm a.bc -o a.asm", "asm a.asm -o"] {
+            let failure = parse(bad).unwrap_err();
+            assert_eq!(failure.exit_code(), EXIT_USAGE, "{}", bad);
+            assert!(failure.message().ends_with(USAGE), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn cli_test_load() {
+        let program = load(b"START\nSTR hi \"hi\"\nSTDOUT hi\n").unwrap();
+        assert!(program.symbols.contains_key("hi"));
+        assert_eq!(program.debug_info.line(0), Some(1));
+        let reloaded = load(&prepare(*program.bytecode, MAX_LEVEL).unwrap().serialize()).unwrap();
+        assert!(reloaded.symbols.is_empty());
+        assert!(reloaded.debug_info.is_empty());
+        assert!(!reloaded.bytecode.is_empty());
+
+        assert_eq!(load(b"START\nADD a b c\n").unwrap_err().exit_code(), EXIT_INVALID);
+        let program = load(b"START\nBEGIN_SCOPE\nNUM x 1\nEND_SCOPE\nSTDOUT x\n").unwrap();
+        assert_eq!(prepare(*program.bytecode, 0).unwrap_err().exit_code(), EXIT_INVALID);
+        assert_eq!(load(&[0xff, 0xfe]).unwrap_err().exit_code(), EXIT_INVALID);
+    }
+
+    #[test]
+    fn cli_test_repl_file() {
+        let dir = std::env::temp_dir();
+        let asm = dir.join(format!("cli_test_repl_file_{}.asm", std::process::id()));
+        let bc = asm.with_extension("bc");
+        std::fs::write(&asm, "NUM k 10\nSTART\nNUM two 2\n").unwrap();
+        std::fs::write(&bc, load(b"START\n").unwrap().bytecode.serialize()).unwrap();
+        let result = open_repl(Some(&asm.display().to_string()));
+        let bytecode = open_repl(Some(&bc.display().to_string()));
+        std::fs::write(&asm, "START\nINT zero 0\nDIV broken zero zero\n").unwrap();
+        let failing = open_repl(Some(&asm.display().to_string()));
+        std::fs::remove_file(&asm).unwrap();
+        std::fs::remove_file(&bc).unwrap();
+        assert!(result.is_ok());
+        assert_eq!(bytecode.err().unwrap().exit_code(), EXIT_INVALID);
+        assert_eq!(failing.err().unwrap().exit_code(), EXIT_RUNTIME);
+        assert_eq!(open_repl(Some(&asm.display().to_string())).err().unwrap().exit_code(), EXIT_IO);
+    }
 }
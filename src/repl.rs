@@ -0,0 +1,294 @@
+use std::collections::HashMap;
+use crate::lexer::asm::{Parser, RunError};
+use crate::lexer::disasm::Disassembler;
+use crate::vm::bytecodes::BytecodeBuilder;
+use crate::vm::io::{Io, StdIo};
+use crate::vm::vm::Executor;
+
+const HELP:&str = "\
+Lines of assembly run as soon as they are entered. A BLOCK runs until an empty line and a FUNC
+until its END, so they can span several lines.
+    :vars          show every variable that holds a value
+    :dump          disassemble everything entered so far
+    :reset         forget every name and value
+    :load <file>   run a file as one piece, the way `run` runs it
+    :help          show this text
+    :quit          leave
+";
+
+/// A line-based text interface that `run_stdio` can drive.
+pub trait Interactive {
+    /// What to show before reading the next line.
+    fn prompt(&self) -> &'static str;
+    /// Handles one line of input.
+    fn eval(&mut self, line:&str) -> Reply;
+}
+
+/// What `Interactive::eval` wants shown.
+#[derive(Debug, PartialEq)]
+pub enum Reply {
+    /// Output of a command, possibly empty.
+    Text(String),
+    /// An error to report. The session carries on; in the REPL a line that does not assemble is
+    /// forgotten, while one that fails at runtime stays in the program along with whatever it did
+    /// before the error.
+    Error(String),
+    Quit
+}
+
+/// An interactive session that assembles and runs each line as it is entered, keeping the names
+/// and values of earlier lines.
+pub struct Repl {
+    parser:Parser,
+    exec:Executor,
+    /// Every name entered so far, for `:dump`.
+    symbols:HashMap<String, u32>,
+    /// Lines of a BLOCK or FUNC that has not been finished yet.
+    pending:Vec<String>
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        return Repl {
+            parser: Parser::new(String::new()),
+            exec: Executor::new(BytecodeBuilder::new().src),
+            symbols: HashMap::new(),
+            pending: vec![]
+        };
+    }
+
+    /// Runs STDOUT and STDIN against `io` instead of the process's standard streams.
+    pub fn with_io(mut self, io:Box<dyn Io>) -> Repl {
+        self.exec = self.exec.with_io(io);
+        return self;
+    }
+
+    fn command(&mut self, command:&str) -> Reply {
+        let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        return match (name, argument.trim()) {
+            ("vars", "") => {
+                let mut names:Vec<(&String, String)> = self.symbols.iter()
+                    .filter_map(|(name, id)| Some((name, self.exec.variable(*id)?)))
+                    .collect();
+                names.sort();
+                Reply::Text(names.into_iter().map(|(name, value)| format!("{} = {}\n", name, value)).collect())
+            },
+            ("dump", "") => match Disassembler::new(self.exec.bytecode()).with_symbols(&self.symbols).disassemble() {
+                Ok(text) => Reply::Text(text),
+                Err(err) => Reply::Error(err),
+            },
+            ("reset", "") => {
+                let io = std::mem::replace(&mut self.exec, Executor::new(BytecodeBuilder::new().src)).into_io();
+                *self = Repl::new().with_io(io);
+                Reply::Text(String::new())
+            },
+            ("load", "") => Reply::Error(String::from(":load needs a file")),
+            ("load", path) => match std::fs::read_to_string(path) {
+                Ok(src) => self.submit(&src, true),
+                Err(err) => Reply::Error(format!("cannot read {}: {}", path, err)),
+            },
+            ("help", "") => Reply::Text(String::from(HELP)),
+            ("quit", "") => Reply::Quit,
+            _ => Reply::Error(format!("unknown command `:{}`, see :help", command)),
+        };
+    }
+
+    /// Runs `src` as a whole program the way `:load` does, saying why if it fails.
+    pub fn load(&mut self, src:&str) -> Result<(), RunError> {
+        return self.append(src, true);
+    }
+
+    /// Assembles `src` after everything entered so far and runs it, as a whole program with its
+    /// own START if `whole` is set, or else as more lines after START.
+    fn append(&mut self, src:&str, whole:bool) -> Result<(), RunError> {
+        let program = self.parser.assemble_more(src, self.exec.bytecode()).map_err(RunError::Assembly)?;
+        self.symbols = program.symbols;
+        if whole {
+            self.exec.run_appended_program(*program.bytecode)?;
+        } else {
+            self.exec.run_appended(*program.bytecode)?;
+        }
+        return Ok(());
+    }
+
+    fn submit(&mut self, src:&str, whole:bool) -> Reply {
+        return match self.append(src, whole) {
+            Ok(()) => Reply::Text(String::new()),
+            Err(err) => Reply::Error(err.to_string()),
+        };
+    }
+}
+
+impl Interactive for Repl {
+    fn prompt(&self) -> &'static str {
+        return if self.pending.is_empty() {"> "} else {".. "};
+    }
+
+    /// Handles a command or a line of assembly.
+    fn eval(&mut self, line:&str) -> Reply {
+        let trimmed = line.trim();
+        if let Some(command) = trimmed.strip_prefix(':') {
+            return self.command(command);
+        }
+        let Some(first) = self.pending.first() else {
+            return match trimmed.split_whitespace().next() {
+                None => Reply::Text(String::new()),
+                Some(word) if word.starts_with('#') => Reply::Text(String::new()),
+                Some("BLOCK") | Some("FUNC") => {
+                    self.pending.push(String::from(line));
+                    Reply::Text(String::new())
+                },
+                Some(_) => self.submit(line, false),
+            };
+        };
+        let finished = if first.split_whitespace().next() == Some("FUNC") {trimmed == "END"} else {trimmed.is_empty()};
+        self.pending.push(String::from(line));
+        if !finished {
+            return Reply::Text(String::new());
+        }
+        let src = std::mem::take(&mut self.pending).join("\n");
+        return self.submit(&src, false);
+    }
+}
+
+/// Runs a session on the process's standard streams until it quits or input ends.
+pub fn run_stdio(session:&mut dyn Interactive) -> std::io::Result<()> {
+    let mut io = StdIo;
+    loop {
+        io.write(session.prompt().as_bytes())?;
+        io.flush()?;
+        let line = io.read_line()?;
+        if line.is_empty() {
+            return Ok(());
+        }
+        match session.eval(line.trim_end_matches(['\n', '\r'])) {
+            Reply::Text(text) => io.write(text.as_bytes())?,
+            Reply::Error(err) => eprintln!("{}", err),
+            Reply::Quit => return Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::repl::{Interactive, Repl, Reply};
+    use crate::vm::io::BufferIo;
+
+    /// Feeds `lines` to a session, returning what the program printed and the replies that were not empty.
+    fn session(lines:&[&str]) -> (String, Vec<Reply>) {
+        let io = BufferIo::new();
+        let mut repl = Repl::new().with_io(Box::new(io.clone()));
+        let replies = lines.iter().map(|line| repl.eval(line)).filter(|reply| *reply != Reply::Text(String::new())).collect();
+        return (io.output(), replies);
+    }
+
+    #[test]
+    fn repl_test_lines_share_state() {
+        let (out, replies) = session(&["NUM a 3", "ADD b a a", "STDOUT b", "STR nl \"\\n\"", "STDOUT nl", ":vars"]);
+        assert_eq!(out, "6\n");
+        assert_eq!(replies, vec![Reply::Text(String::from("a = 3\nb = 6\nnl = \"\\n\"\n"))]);
+    }
+
+    #[test]
+    fn repl_test_blocks_and_functions() {
+        let (out, replies) = session(&[
+            "INT i 0",
+            "INT one 1",
+            "INT three 3",
+            "BLOCK loop",
+            "    STDOUT i",
+            "    ADD i i one",
+            "    LT again i three",
+            "    COND_JUMP again loop",
+            "",
+            "FUNC double x",
+            "",
+            "    ADD y x x",
+            "    RET y",
+            "END",
+            "CALL six double three",
+            "STDOUT six",
+        ]);
+        assert_eq!(out, "0126");
+        assert!(replies.is_empty(), "{:?}", replies);
+    }
+
+    #[test]
+    fn repl_test_functions_see_earlier_lines() {
+        let (out, replies) = session(&["INT k 10", "FUNC f x", "    ADD y x k", "    RET y", "END", "CALL r f k", "STDOUT r"]);
+        assert_eq!(out, "20");
+        assert!(replies.is_empty(), "{:?}", replies);
+    }
+
+    #[test]
+    fn repl_test_errors_and_commands() {
+        let (out, replies) = session(&[
+            "INT a 1",
+            "ADD b a missing",
+            "STDOUT b",
+            "INT zero 0",
+            "DIV c a zero",
+            "BLOCK boom",
+            "    INT kept 2",
+            "    DIV d kept zero",
+            "",
+            "STDOUT kept",
+            ":dump",
+            "STDOUT a",
+            ":reset",
+            "STDOUT a",
+            ":frobnicate",
+            ":load /nonexistent/file.asm",
+            ":quit",
+        ]);
+        assert_eq!(out, "21");
+        let errors:Vec<String> = replies.into_iter().map(|reply| match reply {
+            Reply::Error(err) => err.lines().next().unwrap().to_string(),
+            Reply::Quit => String::from("quit"),
+            // Only :dump prints; the lines that failed at runtime are still in the program.
+            Reply::Text(text) => text.lines().filter(|line| line.contains("DIV")).collect::<Vec<&str>>().join("\n"),
+        }).collect();
+        assert_eq!(errors, vec![
+            "error: undefined name `missing`",
+            "error: undefined name `b`",
+            "runtime error in DIV at offset 8: division by zero",
+            "runtime error in DIV at offset 20: division by zero",
+            "DIV c a zero\n    DIV d kept zero",
+            "error: undefined name `a`",
+            "unknown command `:frobnicate`, see :help",
+            "cannot read /nonexistent/file.asm: No such file or directory (os error 2)",
+            "quit",
+        ]);
+    }
+
+    #[test]
+    fn repl_test_dump_and_load() {
+        let path = std::env::temp_dir().join(format!("repl_test_dump_and_load_{}.asm", std::process::id()));
+        std::fs::write(&path, "JUMP skip\nSTR never \"never\"\nSTDOUT never\nBLOCK skip\nSTR hi \"hi\"\n").unwrap();
+        let (out, replies) = session(&[&format!(":load {}", path.display()), "STDOUT hi", ":dump"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(out, "hi");
+        let Reply::Text(dump) = &replies[0] else {
+            panic!("{:?}", replies);
+        };
+        assert!(dump.starts_with("JUMP skip\nSTR never \"never\"\n"), "{}", dump);
+        assert!(dump.ends_with("STDOUT hi\n"), "{}", dump);
+    }
+
+    #[test]
+    fn repl_test_load_runs_like_run() {
+        let path = std::env::temp_dir().join(format!("repl_test_load_runs_like_run_{}.asm", std::process::id()));
+        std::fs::write(&path, "STR a \"pre\"\nSTDOUT a\nSTART\nSTR b \"post\"\nSTDOUT b\n").unwrap();
+        // Only literals, labels and functions run before START, as with `run`.
+        let (out, replies) = session(&[&format!(":load {}", path.display()), "STDOUT a"]);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(out, "postpre");
+        assert!(replies.is_empty(), "{:?}", replies);
+    }
+}
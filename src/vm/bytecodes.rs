@@ -1,6 +1,6 @@
-use std::{cell::RefCell, collections::{HashMap, HashSet}, ops::Index, rc::{Rc, Weak}};
+use std::{cell::RefCell, collections::{HashMap, HashSet}, rc::Rc};
 
-pub const __MAX_INSTR_INT__:u32 = 0x34;
+pub const __MAX_INSTR_INT__:u32 = 0x50;
 pub const ENDL:u32 = 0xA;
 pub const ALLOCA:u32 = 0xB;
 pub const STORE:u32 = 0xC;
@@ -33,14 +33,20 @@ pub const FMT_NUM:u32 = 0x26;
 pub const START:u32 = 0x27;
 // New
 /// OPEN filevar path mode
+///
+/// `mode` is `r` to read, `w` to truncate and write or `a` to append.
 pub const OPEN:u32 = 0x28;
 /// CLOSE filevar
 pub const CLOSE:u32 = 0x29;
 /// READ strvar filevar
+///
+/// Reads everything left in the file.
 pub const READ:u32 = 0x2A;
 /// LIST listvar item1 item2 item3
 pub const LIST:u32 = 0x2B;
 /// INDEX itemvar listvar index index
+///
+/// Each index steps one list deeper; negative indices count from the end.
 pub const INDEX:u32 = 0x2C;
 /// STORE_INDEX listvar item index index
 pub const STORE_INDEX:u32 = 0x2D;
@@ -57,35 +63,290 @@ pub const DOT:u32 = 0x32;
 
 pub const NEGATIVE:u32 = 0x33;
 
+/// FUNC funcvar param1 param2 param3
+///
+/// Declares a function whose body runs up to the matching END. Falling into a FUNC skips its body.
+pub const FUNC:u32 = 0x34;
+/// CALL resultvar funcvar arg1 arg2 arg3
+///
+/// Binds the arguments to the function's parameters in a new scope and stores the returned value in `resultvar`.
+/// The body sees those parameters and the variables bound before START, not the caller's variables.
+pub const CALL:u32 = 0x35;
+/// RET value
+///
+/// The value is optional; a bare RET returns Null.
+pub const RET:u32 = 0x36;
+/// END
+///
+/// Closes a FUNC body, returning Null if reached.
+pub const END:u32 = 0x37;
+/// INT intvar digits
+///
+/// Encoded like NUM but without a DOT, producing an int instead of a float.
+pub const INT:u32 = 0x38;
+/// CAST_INT intvar var
+///
+/// Floats are truncated toward zero; strings must hold a whole number.
+pub const CAST_INT:u32 = 0x39;
+/// CAST_FLOAT floatvar var
+pub const CAST_FLOAT:u32 = 0x3A;
+
+// DOT, NEGATIVE, INFINITY and NAN only appear in the digit encoded literals of opcode set
+// versions before 5, which the loader migrates to the constant pool.
+
+/// Stands in for the digits of an infinite NUM, optionally after a NEGATIVE.
+pub const INFINITY:u32 = 0x3B;
+/// Stands in for the digits of a NaN NUM.
+pub const NAN:u32 = 0x3C;
+
+/// AND boolvar lhs rhs
+///
+/// AND, OR and NOT take bools only. Both operands are already evaluated, so nothing short-circuits.
+pub const AND:u32 = 0x3D;
+/// OR boolvar lhs rhs
+pub const OR:u32 = 0x3E;
+/// NOT boolvar var
+pub const NOT:u32 = 0x3F;
+/// BAND intvar lhs rhs
+///
+/// BAND, BOR, BXOR, SHL and SHR take ints only.
+pub const BAND:u32 = 0x40;
+/// BOR intvar lhs rhs
+pub const BOR:u32 = 0x41;
+/// BXOR intvar lhs rhs
+pub const BXOR:u32 = 0x42;
+/// SHL intvar value amount
+///
+/// The amount must be between 0 and 63. Bits shifted out are lost.
+pub const SHL:u32 = 0x43;
+/// SHR intvar value amount
+///
+/// Shifts arithmetically, so negative values stay negative.
+pub const SHR:u32 = 0x44;
+/// NEG numvar var
+///
+/// NEG, ABS, FLOOR, CEIL and ROUND keep ints as ints, failing on overflow, and bools count as the
+/// ints 0 and 1. FLOOR, CEIL and ROUND of a float give a float.
+pub const NEG:u32 = 0x45;
+/// ABS numvar var
+pub const ABS:u32 = 0x46;
+/// FLOOR numvar var
+pub const FLOOR:u32 = 0x47;
+/// CEIL numvar var
+pub const CEIL:u32 = 0x48;
+/// ROUND numvar var
+///
+/// Halves round away from zero.
+pub const ROUND:u32 = 0x49;
+/// SQRT floatvar var
+///
+/// SQRT, SIN, COS and LOG always give a float. SQRT of a negative number and LOG of a number that
+/// is not positive are errors.
+pub const SQRT:u32 = 0x4A;
+/// SIN floatvar radians
+pub const SIN:u32 = 0x4B;
+/// COS floatvar radians
+pub const COS:u32 = 0x4C;
+/// LOG floatvar var
+///
+/// The natural logarithm.
+pub const LOG:u32 = 0x4D;
+/// MIN numvar lhs rhs
+///
+/// MIN and MAX promote their operands the way ADD does, so an int and a float give a float.
+pub const MIN:u32 = 0x4E;
+/// MAX numvar lhs rhs
+pub const MAX:u32 = 0x4F;
+
+/// The assembler mnemonic of an instruction opcode.
+pub fn mnemonic(opcode:u32) -> Option<&'static str> {
+    return Some(match opcode {
+        ALLOCA => "ALLOCA",
+        STORE => "STORE",
+        DEL => "DEL",
+        ADD => "ADD",
+        SUB => "SUB",
+        MUL => "MUL",
+        DIV => "DIV",
+        MOD => "MOD",
+        JUMP => "JUMP",
+        BLOCK => "BLOCK",
+        COND_JUMP => "COND_JUMP",
+        EQ => "EQ",
+        GT => "GT",
+        LT => "LT",
+        GTE => "GTE",
+        LTE => "LTE",
+        NUM => "NUM",
+        STDOUT => "STDOUT",
+        STDIN => "STDIN",
+        EXP => "EXP",
+        STR => "STR",
+        FMT => "FMT",
+        BEGIN_SCOPE => "BEGIN_SCOPE",
+        END_SCOPE => "END_SCOPE",
+        NEQ => "NEQ",
+        CAST_NUM => "CAST_NUM",
+        CAST_STR => "CAST_STR",
+        FMT_NUM => "FMT_NUM",
+        START => "START",
+        OPEN => "OPEN",
+        CLOSE => "CLOSE",
+        READ => "READ",
+        LIST => "LIST",
+        INDEX => "INDEX",
+        STORE_INDEX => "STORE_INDEX",
+        PUSH => "PUSH",
+        POP => "POP",
+        WRITE => "WRITE",
+        BOOL => "BOOL",
+        FUNC => "FUNC",
+        CALL => "CALL",
+        RET => "RET",
+        END => "END",
+        INT => "INT",
+        CAST_INT => "CAST_INT",
+        CAST_FLOAT => "CAST_FLOAT",
+        AND => "AND",
+        OR => "OR",
+        NOT => "NOT",
+        BAND => "BAND",
+        BOR => "BOR",
+        BXOR => "BXOR",
+        SHL => "SHL",
+        SHR => "SHR",
+        NEG => "NEG",
+        ABS => "ABS",
+        FLOOR => "FLOOR",
+        CEIL => "CEIL",
+        ROUND => "ROUND",
+        SQRT => "SQRT",
+        SIN => "SIN",
+        COS => "COS",
+        LOG => "LOG",
+        MIN => "MIN",
+        MAX => "MAX",
+        _ => return None
+    });
+}
+
+// BINARY FORMAT
+//
+// A serialized `ByteCode` (`.bc` file) is laid out as follows, all integers little endian:
+//
+//   magic               4 bytes   `BC_MAGIC`
+//   format version      u16       layout of the container itself, see `BC_FORMAT_VERSION`
+//   opcode set version  u16       numbering of the opcodes above, see `OPCODE_SET_VERSION`
+//   string table        u32 entry count, then per entry: u32 id, u32 byte length, utf-8 bytes
+//   constant table      u32 entry count, then per entry: u32 id, u8 tag (0 int, 1 float), 8 byte value
+//                       (format version 2 onwards)
+//   code                u32 word count, then that many u32 words
+
+pub const BC_MAGIC:[u8; 4] = *b"IRBC";
+pub const BC_FORMAT_VERSION:u16 = 2;
+/// The oldest container layout the loader still knows how to read.
+pub const MIN_BC_FORMAT_VERSION:u16 = 1;
+/// Bump whenever an existing opcode is renumbered or its operand layout changes.
+///
+/// 2: added FUNC, CALL, RET and END. Version 1 ids can equal the new opcodes but only ever
+/// appear in operand positions, so version 1 code still runs unchanged.
+/// 3: added INT, CAST_INT and CAST_FLOAT, with the same reasoning for older ids.
+/// 4: NUM literals may use INFINITY and NAN in place of digits.
+/// 5: NUM and INT reference the constant pool instead of spelling out their digits.
+/// 6: added AND, OR, NOT, BAND, BOR, BXOR, SHL and SHR, with the same reasoning for older ids.
+/// 7: added NEG, ABS, FLOOR, CEIL, ROUND, SQRT, SIN, COS, LOG, MIN and MAX, likewise.
+pub const OPCODE_SET_VERSION:u16 = 7;
+/// The oldest opcode set version the loader still knows how to read.
+pub const MIN_OPCODE_SET_VERSION:u16 = 1;
+
+/// A word to write into `ByteCode`. Strings are moved into the constant pool and replaced by their id.
 #[derive(Debug)]
 pub enum ByteType {
     Str(String),
     Num(u32)
 }
 
-impl PartialEq<u32> for ByteType {
-    fn eq(&self, other: &u32) -> bool {
-        if let ByteType::Num(this) = self {
-            return other == this;
-        }
-        return false;
+/// A literal, stored once in `ByteCode` and referenced by id from STR, NUM and INT.
+#[derive(Clone, Debug)]
+pub enum Constant {
+    Int(i64),
+    Float(f64),
+    Str(String)
+}
+
+/// Floats compare by their bits so a NaN constant equals itself.
+impl PartialEq for Constant {
+    fn eq(&self, other: &Self) -> bool {
+        return match (self, other) {
+            (Constant::Int(l0), Constant::Int(r0)) => l0 == r0,
+            (Constant::Float(l0), Constant::Float(r0)) => l0.to_bits() == r0.to_bits(),
+            (Constant::Str(l0), Constant::Str(r0)) => l0 == r0,
+            _ => false,
+        };
     }
 }
 
+/// What an operand word holds, so instructions are decoded by position rather than by value.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Operand {
+    /// A variable, block or function id.
+    Id,
+    /// An id in the constant pool.
+    Const,
+    /// A raw 0 or 1.
+    Flag
+}
+
+/// The layout of an instruction after its opcode.
+#[derive(Clone, Copy, Debug)]
+pub struct Schema {
+    pub operands:&'static [Operand],
+    /// More ids follow the fixed operands, up to the ENDL.
+    pub variadic:bool,
+    pub endl:bool
+}
+
+/// The operand layout of an instruction opcode.
+pub fn schema(opcode:u32) -> Option<Schema> {
+    use Operand::*;
+    let (operands, variadic, endl):(&'static [Operand], bool, bool) = match opcode {
+        START | BEGIN_SCOPE | END_SCOPE | END => (&[], false, false),
+        JUMP => (&[Id], false, false),
+        ALLOCA | DEL | BLOCK | STDOUT | STDIN | CLOSE => (&[Id], false, true),
+        STR | NUM | INT => (&[Id, Const], false, true),
+        BOOL => (&[Id, Flag], false, true),
+        COND_JUMP | STORE | CAST_NUM | CAST_INT | CAST_FLOAT | CAST_STR | READ | WRITE | PUSH | POP | NOT
+        | NEG | ABS | FLOOR | CEIL | ROUND | SQRT | SIN | COS | LOG => (&[Id, Id], false, true),
+        ADD | SUB | MUL | DIV | MOD | EXP | EQ | NEQ | GT | LT | GTE | LTE | FMT_NUM | OPEN
+        | AND | OR | BAND | BOR | BXOR | SHL | SHR | MIN | MAX => (&[Id, Id, Id], false, true),
+        FMT | INDEX | STORE_INDEX | CALL => (&[Id, Id], true, true),
+        LIST | FUNC => (&[Id], true, true),
+        // The returned value is optional.
+        RET => (&[], true, true),
+        _ => return None
+    };
+    return Some(Schema { operands: operands, variadic: variadic, endl: endl });
+}
+
 #[derive(Clone, Debug)]
 pub struct ByteCode {
     bytecode: Vec<u32>,
-    strings: HashMap<u32, String>,
-    current: u32,
+    constants: HashMap<u32, Constant>,
     id_manager: Rc<RefCell<IDManager>>
 }
 
+/// Two programs are equal when they hold the same instructions and constants.
+impl PartialEq for ByteCode {
+    fn eq(&self, other: &Self) -> bool {
+        return self.bytecode == other.bytecode && self.constants == other.constants;
+    }
+}
+
 impl ByteCode {
     pub fn new(id_manager:Rc<RefCell<IDManager>>) -> ByteCode {
         return ByteCode {
             bytecode: vec![],
-            strings: HashMap::new(),
-            current: 0,
+            constants: HashMap::new(),
             id_manager: id_manager
         };
     }
@@ -93,73 +354,293 @@ impl ByteCode {
     pub fn len(&self) -> usize{
         self.bytecode.len()
     }
-    pub fn append(&mut self, mut item: ByteType) {
-        if let ByteType::Str(_str) = item {
-            let cid = RefCell::borrow_mut(&self.id_manager).current_id();
-            self.strings.insert(cid, _str);
-            item = ByteType::Num(cid);
-        }
-        match item {
-            ByteType::Num(_item) => self.bytecode.push(_item),
-            _ => {},
-        }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytecode.is_empty()
+    }
+
+    pub fn append(&mut self, item: ByteType) {
+        let word = match item {
+            ByteType::Str(_str) => self.add_constant(Constant::Str(_str)),
+            ByteType::Num(_item) => _item,
+        };
+        self.bytecode.push(word);
     }
+
     pub fn extend(&mut self, items:Vec<ByteType>) {
-        let mut bc: Vec<u32> = vec![];
-        for item in items.iter() {
-            match item {
-                ByteType::Str(_str) => {
-                    let cid = RefCell::borrow_mut(&self.id_manager).current_id();
-                    self.strings.insert(cid, _str.clone());
-                    bc.push(cid);
-                },
-                ByteType::Num(_num) => {
-                    bc.push(*_num);
-                },
-            }
+        for item in items {
+            self.append(item);
         }
-        self.bytecode.extend(bc);
+    }
+
+    /// A program with no code or constants that takes fresh ids from the same pool as this one.
+    pub fn empty_like(&self) -> ByteCode {
+        return ByteCode::new(self.id_manager.clone());
+    }
+
+    /// Appends the code and constants of `other`, which must take its ids from the same pool as
+    /// this program so none of them clash.
+    pub fn extend_from(&mut self, other:ByteCode) {
+        self.bytecode.extend(other.bytecode);
+        self.constants.extend(other.constants);
+    }
+
+    /// Stores a literal in the constant pool under a fresh id.
+    pub fn add_constant(&mut self, value:Constant) -> u32 {
+        let cid = RefCell::borrow_mut(&self.id_manager).current_id();
+        self.constants.insert(cid, value);
+        return cid;
+    }
+
+    pub fn constant(&self, id:u32) -> Option<&Constant> {
+        return self.constants.get(&id);
     }
 
     pub fn set(&mut self, index: usize, item:u32) {
         self.bytecode[index] = item;
     }
 
-    pub fn at(&self, index: usize) -> ByteType {
-        let cur = self.bytecode[index];
-        if self.strings.contains_key(&cur) {
-            return ByteType::Str(self.strings[&cur].clone());
+    /// The raw word at `index`; its meaning depends on where it sits in the instruction, see `schema`.
+    pub fn at(&self, index: usize) -> u32 {
+        return self.bytecode[index];
+    }
+
+    /// The number of words in the instruction whose opcode is at `index`, including the opcode.
+    pub fn instruction_len(&self, index:usize) -> Result<usize, String> {
+        let opcode = self.bytecode[index];
+        let schema = schema(opcode).ok_or_else(|| format!("Unknown opcode {:#x} at offset {}.", opcode, index))?;
+        let mut len = 1 + schema.operands.len();
+        if schema.variadic {
+            while self.bytecode.get(index + len).is_some_and(|word| *word != ENDL) {
+                len += 1;
+            }
         }
-        else {
-            return ByteType::Num(cur);
+        if schema.endl {
+            if self.bytecode.get(index + len) != Some(&ENDL) {
+                return Err(format!("Expected ENDL at offset {}.", index + len));
+            }
+            len += 1;
         }
+        if index + len > self.bytecode.len() {
+            return Err(format!("Instruction at offset {} is cut short.", index));
+        }
+        return Ok(len);
+    }
+
+    /// Encodes the bytecode into the binary `.bc` format.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(&BC_MAGIC);
+        out.extend_from_slice(&BC_FORMAT_VERSION.to_le_bytes());
+        out.extend_from_slice(&OPCODE_SET_VERSION.to_le_bytes());
+
+        // Sorted so the same program always serializes to the same bytes.
+        let mut constants:Vec<(&u32, &Constant)> = self.constants.iter().collect();
+        constants.sort_by_key(|(id, _)| **id);
+
+        let strings:Vec<(&u32, &String)> = constants.iter()
+            .filter_map(|(id, constant)| if let Constant::Str(string) = constant {Some((*id, string))} else {None})
+            .collect();
+        out.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        for (id, string) in strings {
+            out.extend_from_slice(&id.to_le_bytes());
+            out.extend_from_slice(&(string.len() as u32).to_le_bytes());
+            out.extend_from_slice(string.as_bytes());
+        }
+
+        constants.retain(|(_, constant)| !matches!(constant, Constant::Str(_)));
+        out.extend_from_slice(&(constants.len() as u32).to_le_bytes());
+        for (id, constant) in constants {
+            out.extend_from_slice(&id.to_le_bytes());
+            match constant {
+                Constant::Int(val) => {
+                    out.push(0);
+                    out.extend_from_slice(&val.to_le_bytes());
+                },
+                Constant::Float(val) => {
+                    out.push(1);
+                    out.extend_from_slice(&val.to_le_bytes());
+                },
+                Constant::Str(_) => unreachable!("strings are written to the string table"),
+            }
+        }
+
+        out.extend_from_slice(&(self.bytecode.len() as u32).to_le_bytes());
+        for word in self.bytecode.iter() {
+            out.extend_from_slice(&word.to_le_bytes());
+        }
+        return out;
     }
-}
 
-impl Iterator for ByteCode {
-    type Item = ByteType;
+    /// Decodes bytecode from the binary `.bc` format, validating the header.
+    pub fn deserialize(bytes:&[u8]) -> Result<ByteCode, String> {
+        let mut reader = ByteReader { bytes: bytes, pos: 0 };
 
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.current == self.bytecode.len() as u32 {
-            return None;
+        if reader.take(4)? != BC_MAGIC {
+            return Err(String::from("Not a bytecode file: bad magic number."));
         }
-        let cur = self.bytecode[self.current as usize];
-        self.current += 1;
-        if self.strings.contains_key(&cur) {
-            return Some(ByteType::Str(self.strings[&cur].clone()));
+        let format_version = reader.u16()?;
+        if !(MIN_BC_FORMAT_VERSION..=BC_FORMAT_VERSION).contains(&format_version) {
+            return Err(format!(
+                "Unsupported bytecode format version {}, this VM supports versions {} to {}.",
+                format_version, MIN_BC_FORMAT_VERSION, BC_FORMAT_VERSION
+            ));
+        }
+        let opcode_version = reader.u16()?;
+        if !(MIN_OPCODE_SET_VERSION..=OPCODE_SET_VERSION).contains(&opcode_version) {
+            return Err(format!(
+                "Bytecode was written for opcode set version {}, this VM supports versions {} to {}.",
+                opcode_version, MIN_OPCODE_SET_VERSION, OPCODE_SET_VERSION
+            ));
+        }
+
+        let mut constants = HashMap::new();
+        for _ in 0..reader.u32()? {
+            let id = reader.u32()?;
+            let len = reader.u32()? as usize;
+            let string = String::from_utf8(reader.take(len)?.to_vec())
+                .map_err(|_| format!("String {} is not valid utf-8.", id))?;
+            if constants.insert(id, Constant::Str(string)).is_some() {
+                return Err(format!("String {} is defined twice.", id));
+            }
+        }
+
+        // Format version 1 predates the constant table.
+        let constant_count = if format_version >= 2 {reader.u32()?} else {0};
+        for _ in 0..constant_count {
+            let id = reader.u32()?;
+            let tag = reader.take(1)?[0];
+            let bytes:[u8; 8] = reader.take(8)?.try_into().unwrap();
+            let constant = match tag {
+                0 => Constant::Int(i64::from_le_bytes(bytes)),
+                1 => Constant::Float(f64::from_le_bytes(bytes)),
+                _ => return Err(format!("Constant {} has unknown type tag {}.", id, tag)),
+            };
+            if constants.insert(id, constant).is_some() {
+                return Err(format!("Constant {} is defined twice.", id));
+            }
+        }
+
+        let mut bytecode = vec![];
+        for _ in 0..reader.u32()? {
+            bytecode.push(reader.u32()?);
+        }
+
+        if reader.pos != bytes.len() {
+            return Err(format!("Unexpected trailing data at byte {}.", reader.pos));
+        }
+
+        // Continue handing out ids after the highest one in use so later appends cannot collide.
+        // Any word that is not an opcode may be an id, so all of them count as taken.
+        let mut ids = IDManager::new();
+        ids.existing_ids.extend(bytecode.iter().chain(constants.keys()).copied().filter(|word| *word > __MAX_INSTR_INT__));
+        ids._current_id = ids.existing_ids.iter().copied().max().unwrap_or(__MAX_INSTR_INT__);
+        let id_manager = Rc::new(RefCell::new(ids));
+
+        let mut ret = ByteCode::new(id_manager);
+        ret.bytecode = bytecode;
+        ret.constants = constants;
+        if opcode_version < 5 {
+            ret.migrate_digit_literals()?;
+        }
+        return Ok(ret);
+    }
+
+    /// Rewrites NUM and INT literals that spell out their digits, as written before opcode set
+    /// version 5, into references to the constant pool.
+    fn migrate_digit_literals(&mut self) -> Result<(), String> {
+        let code = std::mem::take(&mut self.bytecode);
+        let mut pos = 0;
+        while pos < code.len() {
+            let opcode = code[pos];
+            let len = match opcode {
+                ENDL => 1,
+                NUM | INT => {
+                    let cid = *code.get(pos + 1).ok_or_else(|| format!("Truncated literal at offset {}.", pos))?;
+                    let mut digits = String::new();
+                    let mut end = pos + 2;
+                    loop {
+                        match code.get(end) {
+                            Some(&ENDL) => break,
+                            Some(&DOT) => digits.push('.'),
+                            Some(&NEGATIVE) => digits.push('-'),
+                            Some(&INFINITY) => digits += "inf",
+                            Some(&NAN) => digits += "NaN",
+                            Some(digit) if *digit < 10 => digits += digit.to_string().as_str(),
+                            _ => return Err(format!("Invalid literal at offset {}.", pos)),
+                        }
+                        end += 1;
+                    }
+                    let constant = if opcode == INT {
+                        digits.parse::<i64>().map(Constant::Int).ok()
+                    } else {
+                        digits.parse::<f64>().map(Constant::Float).ok()
+                    };
+                    let constant = constant.ok_or_else(|| format!("Invalid literal `{}` at offset {}.", digits, pos))?;
+                    let id = self.add_constant(constant);
+                    self.bytecode.extend([opcode, cid, id, ENDL]);
+                    pos = end + 1;
+                    continue
+                },
+                _ => {
+                    let schema = schema(opcode).ok_or_else(|| format!("Unknown opcode {:#x} at offset {}.", opcode, pos))?;
+                    if schema.variadic || schema.endl {
+                        let end = code[pos..].iter().position(|word| *word == ENDL)
+                            .ok_or_else(|| format!("Instruction at offset {} has no ENDL.", pos))?;
+                        end + 1
+                    } else {
+                        1 + schema.operands.len()
+                    }
+                },
+            };
+            let end = (pos + len).min(code.len());
+            self.bytecode.extend_from_slice(&code[pos..end]);
+            pos = end;
         }
-        else {
-            return Some(ByteType::Num(cur));
+        return Ok(());
+    }
+}
+
+struct ByteReader<'a> {
+    bytes:&'a [u8],
+    pos:usize
+}
+
+impl<'a> ByteReader<'a> {
+    fn take(&mut self, len:usize) -> Result<&'a [u8], String> {
+        if self.bytes.len() - self.pos < len {
+            return Err(format!("Unexpected end of bytecode file at byte {}.", self.bytes.len()));
         }
+        let ret = &self.bytes[self.pos..self.pos + len];
+        self.pos += len;
+        return Ok(ret);
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let mut buf = [0; 2];
+        buf.copy_from_slice(self.take(2)?);
+        return Ok(u16::from_le_bytes(buf));
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let mut buf = [0; 4];
+        buf.copy_from_slice(self.take(4)?);
+        return Ok(u32::from_le_bytes(buf));
     }
 }
 
 #[derive(Debug)]
-struct IDManager {
+pub struct IDManager {
     _current_id:u32,
     existing_ids:HashSet<u32>,
 }
 
+impl Default for IDManager {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl IDManager {
 
     pub fn new() -> IDManager {
@@ -169,29 +650,23 @@ impl IDManager {
         }
     }
 
+    /// Hands out the next free id, starting over from the lowest once `u32::MAX` is reached.
     fn current_id(&mut self) -> u32 {
-        self._current_id += 0x1;
-        while self.existing_ids.contains(&self._current_id) {
-            self._current_id += 0x1;
+        loop {
+            self._current_id = self._current_id.checked_add(0x1).unwrap_or(__MAX_INSTR_INT__ + 0x1);
+            if self.existing_ids.insert(self._current_id) {
+                return self._current_id
+            }
         }
-        return self._current_id
     }
     
-    fn add_id(&mut self, id:u32) -> Result<(), String> {
+    pub fn add_id(&mut self, id:u32) -> Result<(), String> {
         if self.existing_ids.contains(&id) {
             return Err(format!("The dynamic id {} was instantiated twice.", id))
         }
         self.existing_ids.insert(id);
         Ok(())
     }
-
-    fn remove_id(&mut self, id:u32) -> Result<(), String> {
-        if !self.existing_ids.contains(&id) {
-            return Err(format!("The dynamic id {} was deleted twice.", id))
-        }
-        self.existing_ids.remove(&id);
-        Ok(())
-    }
 }
 
 // BYTECODE BUILDER
@@ -201,6 +676,12 @@ pub struct BytecodeBuilder {
     pub src:Box<ByteCode>
 }
 
+impl Default for BytecodeBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl BytecodeBuilder {
     pub fn new() -> BytecodeBuilder {
         let id_manager = Rc::new(RefCell::new(IDManager::new()));
@@ -210,6 +691,15 @@ impl BytecodeBuilder {
         };
     }
     
+    /// A builder for code to append to `bytecode`, handing out ids from the same pool.
+    pub fn continuing(bytecode:&ByteCode) -> BytecodeBuilder {
+        let src = bytecode.empty_like();
+        return BytecodeBuilder {
+            id_manager: src.id_manager.clone(),
+            src: Box::new(src)
+        };
+    }
+
     fn conv_vec_bt_num(vector:Vec<u32>) -> Vec<ByteType> {
         let mut new_vec = vec![];
         for bt in vector {
@@ -218,6 +708,12 @@ impl BytecodeBuilder {
         return new_vec;
     }
 
+    /// Claims a specific id so it is never handed out by the builder. Fails if the id was
+    /// already claimed or handed out.
+    pub fn reserve_id(&mut self, id:u32) -> Result<(), String> {
+        return RefCell::borrow_mut(&self.id_manager).add_id(id);
+    }
+
     fn get_cid(&mut self, cid:Option<u32>) -> u32 {
         return match cid {
             Some(__cid) => __cid,
@@ -281,9 +777,7 @@ impl BytecodeBuilder {
     }
 
     pub fn write_del(&mut self, id:u32) -> u32 {
-
-        RefCell::borrow_mut(&self.id_manager).remove_id(id);
-
+        // The id stays claimed: the front-end may still refer to it, e.g. to define it again.
         self.src.as_mut().extend(Self::conv_vec_bt_num(vec![DEL, id, ENDL]));
         return id;
     }
@@ -330,6 +824,139 @@ impl BytecodeBuilder {
         return cid;
     }
 
+    pub fn write_and(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![AND, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_or(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![OR, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_band(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![BAND, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_bor(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![BOR, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_bxor(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![BXOR, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_shl(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![SHL, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_shr(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![SHR, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_not(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![NOT, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_neg(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![NEG, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_abs(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![ABS, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_floor(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![FLOOR, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_ceil(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![CEIL, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_round(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![ROUND, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_sqrt(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![SQRT, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_sin(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![SIN, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_cos(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![COS, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_log(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![LOG, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_min(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![MIN, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
+    pub fn write_max(&mut self, lhs:u32, rhs:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![MAX, cid, lhs, rhs, ENDL]));
+        return cid;
+    }
+
     pub fn write_jump(&mut self, block:u32) -> u32 {
         self.src.as_mut().extend(Self::conv_vec_bt_num(vec![JUMP, block]));
         return block;
@@ -347,6 +974,34 @@ impl BytecodeBuilder {
         return cid;
     }
 
+    /// Writes a FUNC header. Parameters without an id are given a fresh one; returns the
+    /// function id and the parameter ids in order.
+    pub fn write_func(&mut self, params:Vec<Option<u32>>, _cid:Option<u32>) -> (u32, Vec<u32>) {
+        let cid = self.get_cid(_cid);
+        let params:Vec<u32> = params.into_iter().map(|param| self.get_cid(param)).collect();
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num([[FUNC, cid].as_slice(), params.as_slice(), [ENDL].as_slice()].concat()));
+        return (cid, params);
+    }
+
+    pub fn write_end(&mut self) -> () {
+        self.src.as_mut().append(ByteType::Num(END));
+    }
+
+    pub fn write_call(&mut self, func:u32, args:Vec<u32>, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num([[CALL, cid, func].as_slice(), args.as_slice(), [ENDL].as_slice()].concat()));
+        return cid;
+    }
+
+    pub fn write_ret(&mut self, value:Option<u32>) -> () {
+        let mut instr = vec![RET];
+        instr.extend(value);
+        instr.push(ENDL);
+        self.src.as_mut().extend(Self::conv_vec_bt_num(instr));
+    }
+
     pub fn write_begin_scope(&mut self) -> () {
         self.src.as_mut().append(ByteType::Num(BEGIN_SCOPE));
     }
@@ -359,35 +1014,26 @@ impl BytecodeBuilder {
         self.src.as_mut().append(ByteType::Num(START));
     }
 
-    pub fn write_num(&mut self, _num:f32, _cid:Option<u32>) -> u32 {
+    pub fn write_num(&mut self, _num:f64, _cid:Option<u32>) -> u32 {
         let cid = self.get_cid(_cid);
+        let constant = self.src.add_constant(Constant::Float(_num));
 
-        let mut num = vec![];
-        for i in _num.to_string().chars() {
-            match i {
-                '.' => {
-                    num.push(DOT);
-                }
-                '0'|'1'|'2'|'3'|'4'|'5'|'6'|'7'|'8'|'9' => {
-                    num.push(i.to_digit(10).unwrap());
-                }
-                '-' => {
-                    num.push(NEGATIVE);
-                }
-                _ => {
-                    panic!("NUM type only includes numbers")
-                }
-            }
-        }
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![NUM, cid, constant, ENDL]));
+        return cid;
+    }
+
+    pub fn write_int(&mut self, _int:i64, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+        let constant = self.src.add_constant(Constant::Int(_int));
 
-        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![vec![NUM, cid].as_slice(), num.as_slice(), vec![ENDL].as_slice()].concat()));
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![INT, cid, constant, ENDL]));
         return cid;
     }
 
     pub fn write_bool(&mut self, _bool:bool, _cid:Option<u32>) -> u32 {
         let cid = self.get_cid(_cid);
 
-        let mut tf = if _bool {ByteType::Num(1)} else {ByteType::Num(0)};
+        let tf = if _bool {ByteType::Num(1)} else {ByteType::Num(0)};
 
         self.src.as_mut().extend(vec![ByteType::Num(BOOL), ByteType::Num(cid), tf, ByteType::Num(ENDL)]);
         return cid;
@@ -400,6 +1046,20 @@ impl BytecodeBuilder {
         return cid;
     }
 
+    pub fn write_cast_int(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![CAST_INT, cid, id, ENDL]));
+        return cid;
+    }
+
+    pub fn write_cast_float(&mut self, id:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![CAST_FLOAT, cid, id, ENDL]));
+        return cid;
+    }
+
     pub fn write_cast_str(&mut self, id:u32, _cid:Option<u32>) -> u32 {
         let cid = self.get_cid(_cid);
 
@@ -417,7 +1077,7 @@ impl BytecodeBuilder {
     pub fn write_fmt(&mut self, string:u32, items:Vec<u32>, _cid:Option<u32>) -> u32 {
         let cid = self.get_cid(_cid);
 
-        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![vec![FMT, cid, string].as_slice(), items.as_slice(), vec![ENDL].as_slice()].concat()));
+        self.src.as_mut().extend(Self::conv_vec_bt_num([[FMT, cid, string].as_slice(), items.as_slice(), [ENDL].as_slice()].concat()));
         return cid;
     }
 
@@ -432,6 +1092,57 @@ impl BytecodeBuilder {
         self.src.as_mut().extend(Self::conv_vec_bt_num(vec![STDOUT, out, ENDL]));
     }
 
+    pub fn write_open(&mut self, path:u32, mode:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![OPEN, cid, path, mode, ENDL]));
+        return cid;
+    }
+
+    pub fn write_close(&mut self, file:u32) -> () {
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![CLOSE, file, ENDL]));
+    }
+
+    pub fn write_read(&mut self, file:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![READ, cid, file, ENDL]));
+        return cid;
+    }
+
+    pub fn write_write(&mut self, file:u32, value:u32) -> () {
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![WRITE, file, value, ENDL]));
+    }
+
+    pub fn write_list(&mut self, items:Vec<u32>, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num([[LIST, cid].as_slice(), items.as_slice(), [ENDL].as_slice()].concat()));
+        return cid;
+    }
+
+    pub fn write_index(&mut self, list:u32, indices:Vec<u32>, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num([[INDEX, cid, list].as_slice(), indices.as_slice(), [ENDL].as_slice()].concat()));
+        return cid;
+    }
+
+    pub fn write_store_index(&mut self, list:u32, item:u32, indices:Vec<u32>) -> () {
+        self.src.as_mut().extend(Self::conv_vec_bt_num([[STORE_INDEX, list, item].as_slice(), indices.as_slice(), [ENDL].as_slice()].concat()));
+    }
+
+    pub fn write_push(&mut self, list:u32, item:u32) -> () {
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![PUSH, list, item, ENDL]));
+    }
+
+    pub fn write_pop(&mut self, list:u32, _cid:Option<u32>) -> u32 {
+        let cid = self.get_cid(_cid);
+
+        self.src.as_mut().extend(Self::conv_vec_bt_num(vec![POP, cid, list, ENDL]));
+        return cid;
+    }
+
     pub fn write_stdin(&mut self, _cid:Option<u32>) -> u32 {
         let cid = self.get_cid(_cid);
 
@@ -440,3 +1151,80 @@ impl BytecodeBuilder {
     }
 
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::disasm::Disassembler;
+
+    fn sample() -> ByteCode {
+        let mut bb = BytecodeBuilder::new();
+        let hello = bb.write_str(String::from("Hello world!\n"), None);
+        let num = bb.write_num(-1.289893, None);
+        bb.write_start();
+        bb.write_stdout(hello);
+        bb.write_stdout(num);
+        return *bb.src;
+    }
+
+    #[test]
+    fn bc_test_round_trip() {
+        let bytecode = sample();
+        let bytes = bytecode.serialize();
+        let loaded = ByteCode::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.bytecode, bytecode.bytecode);
+        assert_eq!(loaded.constants, bytecode.constants);
+        assert_eq!(loaded.serialize(), bytes);
+    }
+
+    #[test]
+    fn bc_test_rejects_bad_magic() {
+        let mut bytes = sample().serialize();
+        bytes[0] = b'X';
+        assert!(ByteCode::deserialize(&bytes).unwrap_err().contains("magic"));
+    }
+
+    #[test]
+    fn bc_test_migrates_digit_literals() {
+        // A format version 1 file from opcode set 4, where literals spelled out their digits.
+        let code = [
+            NUM, 0x40, NEGATIVE, 2, DOT, 5, ENDL,
+            INT, 0x41, 4, 2, ENDL,
+            NUM, 0x42, NEGATIVE, INFINITY, ENDL,
+            STR, 0x43, 0x44, ENDL,
+            START,
+            STDOUT, 0x40, ENDL
+        ];
+        let mut bytes = BC_MAGIC.to_vec();
+        bytes.extend_from_slice(&1u16.to_le_bytes());
+        bytes.extend_from_slice(&4u16.to_le_bytes());
+        bytes.extend_from_slice(&1u32.to_le_bytes());
+        bytes.extend_from_slice(&0x44u32.to_le_bytes());
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(b"hi");
+        bytes.extend_from_slice(&(code.len() as u32).to_le_bytes());
+        for word in code {
+            bytes.extend_from_slice(&word.to_le_bytes());
+        }
+
+        let bytecode = ByteCode::deserialize(&bytes).unwrap();
+        let text = Disassembler::new(&bytecode).disassemble().unwrap();
+        assert_eq!(text, "NUM %64 -2.5\nINT %65 42\nNUM %66 -inf\nSTR %67 \"hi\"\nSTART\n    STDOUT %64\n");
+        // The migrated program saves in the current format and loads back unchanged.
+        assert!(ByteCode::deserialize(&bytecode.serialize()).unwrap() == bytecode);
+    }
+
+    #[test]
+    fn bc_test_rejects_incompatible_opcode_set() {
+        let mut bytes = sample().serialize();
+        bytes[6..8].copy_from_slice(&(OPCODE_SET_VERSION + 1).to_le_bytes());
+        assert!(ByteCode::deserialize(&bytes).unwrap_err().contains("opcode set version"));
+    }
+
+    #[test]
+    fn bc_test_rejects_truncated() {
+        let bytes = sample().serialize();
+        assert!(ByteCode::deserialize(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ByteCode::deserialize(&bytes[..6]).is_err());
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::HashMap, ops::Range};
+
+use super::bytecodes::ByteCode;
+use super::error::VmError;
+use super::instr::{decode, Decoded, Instr, Resolver};
+use crate::lexer::disasm::Disassembler;
+
+/// How control gets from one basic block to another.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EdgeKind {
+    /// Execution runs off the end of a block into the next one, including into a BLOCK.
+    FallThrough,
+    Jump,
+    /// A COND_JUMP whose condition held.
+    Branch,
+    /// Execution that reaches a FUNC carries on after its END.
+    Skip,
+    /// Before START jumps are not taken, so the prelude runs straight on past them.
+    Prelude,
+    /// A CALL enters a function, and control comes back to the instruction after the CALL.
+    Call
+}
+
+impl EdgeKind {
+    fn name(&self) -> &'static str {
+        return match self {
+            EdgeKind::FallThrough => "",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Branch => "branch",
+            EdgeKind::Skip => "skip",
+            EdgeKind::Prelude => "prelude",
+            EdgeKind::Call => "call",
+        };
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Edge {
+    pub from:usize,
+    pub to:usize,
+    pub kind:EdgeKind
+}
+
+/// A run of instructions that is only ever entered at the top and left at the bottom.
+#[derive(Clone, Debug, PartialEq)]
+pub struct BasicBlock {
+    /// Indices into `Cfg.decoded.instrs`.
+    pub instrs:Range<usize>,
+    /// The id of the BLOCK the basic block starts with, if any.
+    pub label:Option<u32>,
+    /// The function whose body holds this block, as an index into `Decoded.functions`.
+    pub function:Option<usize>,
+    /// Whether the block comes before START and outside of any function.
+    pub prelude:bool
+}
+
+/// The control-flow graph of a program.
+///
+/// Blocks start at BLOCK, START, the start of a function body and after any instruction that
+/// transfers control. Analyses following a single function should ignore `Call` edges, and
+/// analyses of code after START should ignore `Prelude` ones.
+pub struct Cfg<'a> {
+    bytecode:&'a ByteCode,
+    pub decoded:Decoded,
+    pub resolver:Resolver,
+    pub blocks:Vec<BasicBlock>,
+    pub edges:Vec<Edge>,
+    /// The block holding START.
+    pub start:Option<usize>,
+    /// The basic block of each instruction.
+    block_of:Vec<usize>
+}
+
+impl<'a> Cfg<'a> {
+    pub fn new(bytecode:&'a ByteCode) -> Result<Cfg<'a>, VmError> {
+        let mut resolver = Resolver::new();
+        let decoded = decode(bytecode, &mut resolver)?;
+        let instrs = &decoded.instrs;
+        let start = instrs.iter().position(|instr| *instr == Instr::Start);
+
+        // Where each block starts, with the end of the program closing the last one.
+        let mut leaders = vec![false; instrs.len() + 1];
+        leaders[instrs.len()] = true;
+        for (index, instr) in instrs.iter().enumerate() {
+            match instr {
+                Instr::Block(_) | Instr::Start => leaders[index] = true,
+                Instr::Jump(..) | Instr::CondJump(..) | Instr::Func(_) | Instr::Ret(_) | Instr::End => leaders[index + 1] = true,
+                _ => {}
+            }
+        }
+        let mut function_of = vec![None; instrs.len()];
+        for (func, function) in decoded.functions.iter().enumerate() {
+            function_of[function.body..=function.end].fill(Some(func));
+        }
+
+        let mut blocks = vec![];
+        let mut block_of = vec![0; instrs.len()];
+        let mut first = 0;
+        for index in 1..=instrs.len() {
+            if !leaders[index] {
+                continue
+            }
+            let label = if let Instr::Block(label) = instrs[first] {Some(label)} else {None};
+            let function = function_of[first];
+            let prelude = function.is_none() && start.is_none_or(|start| first < start);
+            block_of[first..index].fill(blocks.len());
+            blocks.push(BasicBlock { instrs: first..index, label: label, function: function, prelude: prelude });
+            first = index;
+        }
+
+        let mut edges = vec![];
+        for (from, block) in blocks.iter().enumerate() {
+            let mut edge = |to:usize, kind:EdgeKind| if to < instrs.len() {
+                edges.push(Edge { from: from, to: block_of[to], kind: kind });
+            };
+            for index in block.instrs.clone() {
+                if let Instr::Call(_, _, Some(func), _) = &instrs[index] {
+                    edge(decoded.functions[*func].body, EdgeKind::Call);
+                }
+            }
+            let next = block.instrs.end;
+            // Targets are the instruction after a label, so edges go to the label itself, which
+            // also exists when it is the last instruction of the program.
+            match &instrs[next - 1] {
+                Instr::Jump(_, target) => {
+                    if let Some(target) = target {
+                        edge(*target - 1, EdgeKind::Jump);
+                    }
+                    if block.prelude {
+                        edge(next, EdgeKind::Prelude);
+                    }
+                },
+                Instr::CondJump(_, _, target) => {
+                    if let Some(target) = target {
+                        edge(*target - 1, EdgeKind::Branch);
+                    }
+                    edge(next, EdgeKind::FallThrough);
+                },
+                Instr::Func(func) => edge(decoded.functions[*func].end + 1, EdgeKind::Skip),
+                Instr::Ret(_) if block.prelude => edge(next, EdgeKind::Prelude),
+                Instr::Ret(_) | Instr::End => {},
+                _ => edge(next, EdgeKind::FallThrough),
+            }
+        }
+
+        return Ok(Cfg {
+            bytecode: bytecode,
+            start: start.map(|start| block_of[start]),
+            decoded: decoded,
+            resolver: resolver,
+            blocks: blocks,
+            edges: edges,
+            block_of: block_of
+        });
+    }
+
+    /// The basic block holding the instruction at `index` in `decoded.instrs`.
+    pub fn block_of(&self, index:usize) -> usize {
+        return self.block_of[index];
+    }
+
+    pub fn successors(&self, block:usize) -> impl Iterator<Item = &Edge> {
+        return self.edges.iter().filter(move |edge| edge.from == block);
+    }
+
+    pub fn predecessors(&self, block:usize) -> impl Iterator<Item = &Edge> {
+        return self.edges.iter().filter(move |edge| edge.to == block);
+    }
+
+    /// Renders the graph in Graphviz DOT, naming ids with `symbols` as the disassembler does.
+    pub fn to_dot(&self, symbols:&HashMap<String, u32>) -> Result<String, String> {
+        let mut disassembler = Disassembler::new(self.bytecode).with_symbols(symbols);
+        let mut out = String::from("digraph cfg {\n    node [shape=box, fontname=\"monospace\"];\n");
+        for (index, block) in self.blocks.iter().enumerate() {
+            let mut label = format!("b{}", index);
+            if block.prelude {
+                label += " (prelude)";
+            }
+            label += "\\l";
+            for instr in block.instrs.clone() {
+                let (offset, _) = self.decoded.origins[instr];
+                label += &escape(&disassembler.instruction(offset)?);
+                label += "\\l";
+            }
+            out += &format!("    b{} [label=\"{}\"];\n", index, label);
+        }
+        for edge in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Prelude | EdgeKind::Call => ", style=dashed",
+                _ => "",
+            };
+            out += &format!("    b{} -> b{} [label=\"{}\"{}];\n", edge.from, edge.to, edge.kind.name(), style);
+        }
+        out += "}\n";
+        return Ok(out);
+    }
+}
+
+/// Escapes text for a double quoted DOT string.
+fn escape(text:&str) -> String {
+    return text.replace('\\', "\\\\").replace('"', "\\\"");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::vm::cfg::{Cfg, Edge, EdgeKind};
+
+    const LOOP:&str = "
+        NUM ind 0
+        NUM inc 1
+        NUM limit 10
+
+        BLOCK loop
+            ADD ind ind inc
+        LT again ind limit
+        COND_JUMP again loop
+        JUMP finished
+
+        FUNC twice value
+            ADD doubled value value
+            RET doubled
+        END
+
+        START
+            JUMP loop
+            BLOCK finished
+            CALL four twice inc
+            STR text \"say \\\"hi\\\"\"
+    ";
+
+    #[test]
+    fn cfg_test_blocks_and_edges() {
+        let program = Parser::new(String::from(LOOP)).assemble().unwrap();
+        let cfg = Cfg::new(&program.bytecode).unwrap();
+        let ranges:Vec<_> = cfg.blocks.iter().map(|block| block.instrs.clone()).collect();
+        // prelude, loop, JUMP finished, FUNC, body, END, START, finished
+        assert_eq!(ranges, vec![0..3, 3..7, 7..8, 8..9, 9..11, 11..12, 12..14, 14..17]);
+        assert_eq!(cfg.blocks[1].label, Some(program.symbols["loop"]));
+        assert!(cfg.blocks[..4].iter().all(|block| block.prelude && block.function.is_none()));
+        assert_eq!((cfg.blocks[4].function, cfg.blocks[5].function), (Some(0), Some(0)));
+        assert!(cfg.blocks[4..].iter().all(|block| !block.prelude));
+        assert_eq!(cfg.start, Some(6));
+        let edge = |from, to, kind| Edge { from: from, to: to, kind: kind };
+        assert_eq!(cfg.edges, vec![
+            edge(0, 1, EdgeKind::FallThrough),
+            edge(1, 1, EdgeKind::Branch),
+            edge(1, 2, EdgeKind::FallThrough),
+            edge(2, 7, EdgeKind::Jump),
+            edge(2, 3, EdgeKind::Prelude),
+            edge(3, 6, EdgeKind::Skip),
+            edge(6, 1, EdgeKind::Jump),
+            edge(7, 4, EdgeKind::Call),
+        ]);
+        assert_eq!(cfg.predecessors(1).count(), 3);
+        assert_eq!(cfg.successors(2).map(|edge| edge.to).collect::<Vec<_>>(), vec![7, 3]);
+        assert_eq!(cfg.block_of(13), 6);
+    }
+
+    #[test]
+    fn cfg_test_dot() {
+        let program = Parser::new(String::from(LOOP)).assemble().unwrap();
+        let dot = Cfg::new(&program.bytecode).unwrap().to_dot(&program.symbols).unwrap();
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains("    b1 [label=\"b1 (prelude)\\lBLOCK loop\\lADD ind ind inc\\lLT again ind limit\\lCOND_JUMP again loop\\l\"];\n"), "{}", dot);
+        assert!(dot.contains("STR text \\\"say \\\\\\\"hi\\\\\\\"\\\"\\l"), "{}", dot);
+        assert!(dot.contains("    b1 -> b1 [label=\"branch\"];\n"), "{}", dot);
+        assert!(dot.contains("    b7 -> b4 [label=\"call\", style=dashed];\n"), "{}", dot);
+        assert!(dot.ends_with("}\n"));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fmt;
+
+/// Maps the offset of each instruction to the source line it was assembled from.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DebugInfo {
+    /// Pairs of offset and 1-based line, in order of offset.
+    lines:Vec<(usize, usize)>
+}
+
+impl DebugInfo {
+    pub fn new() -> DebugInfo {
+        return DebugInfo::default();
+    }
+
+    /// Records that the instruction at `offset` came from `line`. Offsets must be added in order.
+    pub fn add(&mut self, offset:usize, line:usize) {
+        debug_assert!(self.lines.last().is_none_or(|(last, _)| *last < offset));
+        self.lines.push((offset, line));
+    }
+
+    /// The line the instruction starting at `offset` came from.
+    pub fn line(&self, offset:usize) -> Option<usize> {
+        let index = self.lines.binary_search_by_key(&offset, |(offset, _)| *offset).ok()?;
+        return Some(self.lines[index].1);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.lines.is_empty();
+    }
+}
+
+/// Where `Executor::run_until_breakpoint` stops, just before running the instruction concerned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Breakpoint {
+    /// The instruction starting at this offset.
+    Offset(usize),
+    /// The first instruction of the block with this id, whether jumped to or fallen into.
+    Block(u32),
+    /// The instructions assembled from this source line, see `Executor::with_debug_info`.
+    Line(usize)
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Breakpoint::Offset(offset) => write!(f, "offset {}", offset),
+            Breakpoint::Block(block) => write!(f, "block {}", block),
+            Breakpoint::Line(line) => write!(f, "line {}", line),
+        };
+    }
+}
+
+/// Why `Executor::step` or `Executor::run_until_breakpoint` returned.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Pause {
+    /// One instruction ran.
+    Step,
+    /// The next instruction is at a breakpoint.
+    Breakpoint,
+    /// Nothing is left to run.
+    Finished
+}
+
+/// One scope of a paused program, as returned by `Executor::frames`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// 1 for the outermost scope.
+    pub depth:usize,
+    /// The function whose call opened the scope, if a CALL did.
+    pub function:Option<u32>,
+    /// The variables bound in this scope by id, including ones an inner scope hides, with their
+    /// values shown as `Executor::variable` does.
+    pub variables:Vec<(u32, String)>
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::vm::debug::{Breakpoint, Frame, Pause};
+    use crate::vm::io::BufferIo;
+    use crate::vm::vm::Executor;
+
+    const LOOP:&str = "INT i 0
+INT one 1
+INT three 3
+START
+BLOCK loop
+    ADD i i one
+    LT again i three
+    COND_JUMP again loop
+STDOUT i
+";
+
+    fn executor(src:&str) -> (Executor, BufferIo, std::collections::HashMap<String, u32>) {
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let io = BufferIo::new();
+        let exec = Executor::new(program.bytecode).with_debug_info(program.debug_info).with_io(Box::new(io.clone()));
+        return (exec, io, program.symbols);
+    }
+
+    #[test]
+    fn debug_test_lines() {
+        let program = Parser::new(String::from(LOOP)).assemble().unwrap();
+        assert_eq!(program.debug_info.line(0), Some(1));
+        assert_eq!(program.debug_info.line(12), Some(4));
+        assert_eq!(program.debug_info.line(13), Some(5));
+        assert_eq!(program.debug_info.line(1), None);
+    }
+
+    #[test]
+    fn debug_test_step() {
+        let (mut exec, io, symbols) = executor(LOOP);
+        assert_eq!(exec.position(), None);
+        let mut lines = vec![];
+        while exec.step().unwrap() == Pause::Step {
+            lines.push(exec.line());
+        }
+        // The labels are passed over, and the loop runs three times.
+        let expected:Vec<Option<usize>> = [2, 3, 4, 6, 7, 8, 6, 7, 8, 6, 7, 8, 9].into_iter().map(Some).collect();
+        assert_eq!(lines, expected);
+        assert_eq!(exec.position(), None);
+        assert_eq!(exec.step().unwrap(), Pause::Finished);
+        assert_eq!(io.output(), "3");
+        assert_eq!(exec.variable(symbols["i"]), Some(String::from("3")));
+    }
+
+    #[test]
+    fn debug_test_breakpoints() {
+        let (mut exec, io, symbols) = executor(LOOP);
+        exec.add_breakpoint(Breakpoint::Block(symbols["loop"]));
+        exec.add_breakpoint(Breakpoint::Line(9));
+        let mut stops = vec![];
+        while exec.run_until_breakpoint().unwrap() == Pause::Breakpoint {
+            stops.push((exec.line(), exec.variable(symbols["i"])));
+        }
+        assert_eq!(stops, vec![
+            (Some(6), Some(String::from("0"))),
+            (Some(6), Some(String::from("1"))),
+            (Some(6), Some(String::from("2"))),
+            (Some(9), Some(String::from("3"))),
+        ]);
+        assert_eq!(io.output(), "3");
+
+        let (mut exec, _, _) = executor(LOOP);
+        exec.add_breakpoint(Breakpoint::Offset(0));
+        assert_eq!(exec.run_until_breakpoint().unwrap(), Pause::Breakpoint);
+        assert_eq!(exec.position(), Some(0));
+        assert!(exec.remove_breakpoint(Breakpoint::Offset(0)));
+        assert!(!exec.remove_breakpoint(Breakpoint::Offset(0)));
+        assert_eq!(exec.run_until_breakpoint().unwrap(), Pause::Finished);
+    }
+
+    #[test]
+    fn debug_test_consecutive_labels() {
+        let src = "INT i 0
+INT one 1
+INT two 2
+START
+BLOCK outer
+BLOCK inner
+    ADD i i one
+    LT again i two
+    COND_JUMP again outer
+STDOUT i
+";
+        // Either label stops at the ADD, whether the loop falls into it or jumps back.
+        for label in ["outer", "inner"] {
+            let (mut exec, _, symbols) = executor(src);
+            exec.add_breakpoint(Breakpoint::Block(symbols[label]));
+            let mut stops = vec![];
+            while exec.run_until_breakpoint().unwrap() == Pause::Breakpoint {
+                stops.push((exec.line(), exec.variable(symbols["i"])));
+            }
+            assert_eq!(stops, vec![(Some(7), Some(String::from("0"))), (Some(7), Some(String::from("1")))], "{}", label);
+        }
+    }
+
+    #[test]
+    fn debug_test_frames_and_errors() {
+        let (mut exec, _, symbols) = executor("FUNC half n
+    NUM two 2
+    DIV out n two
+    RET out
+END
+START
+NUM n 5
+BEGIN_SCOPE
+    NUM n 6
+    CALL result half n
+END_SCOPE
+INT zero 0
+DIV broken n zero
+");
+        exec.add_breakpoint(Breakpoint::Line(4));
+        assert_eq!(exec.run_until_breakpoint().unwrap(), Pause::Breakpoint);
+        let (n, two, out, half) = (symbols["n"], symbols["two"], symbols["out"], symbols["half"]);
+        // `n` is both the parameter and the caller's variable, so each scope has a binding of it.
+        assert_eq!(exec.frames(), vec![
+            Frame { depth: 1, function: None, variables: vec![(n, String::from("5"))] },
+            Frame { depth: 2, function: None, variables: vec![(n, String::from("6"))] },
+            Frame { depth: 3, function: Some(half), variables: vec![(n, String::from("6")), (two, String::from("2")), (out, String::from("3"))] },
+        ]);
+        let err = exec.run_until_breakpoint().unwrap_err();
+        assert_eq!(err.to_string(), "runtime error in DIV at offset 37: division by zero");
+        assert_eq!(exec.frames().len(), 1);
+        assert_eq!(exec.step().unwrap(), Pause::Finished);
+    }
+}
@@ -0,0 +1,67 @@
+use std::fmt;
+
+use super::bytecodes as bc;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum VmErrorKind {
+    /// An instruction referenced an id that holds no value.
+    UnknownMemory(u32),
+    /// An operation was applied to values it does not support.
+    TypeMismatch(String),
+    DivisionByZero,
+    /// Integer arithmetic left the range of an i64; holds the operation's mnemonic.
+    Overflow(String),
+    /// A list was indexed outside of its bounds; holds the index and the list's length.
+    IndexOutOfBounds(i64, usize),
+    /// A jump targeted a block that was never defined.
+    MissingBlock(u32),
+    /// A CALL named a function that was never defined.
+    MissingFunction(u32),
+    /// Calls nested deeper than the executor's maximum call depth, which this holds.
+    StackOverflow(usize),
+    /// The bytecode does not have the shape the opcode expects.
+    MalformedInstruction(String),
+    /// A math intrinsic was given a number outside of its domain, such as the SQRT of -1.
+    Domain(String),
+    /// Reading or writing outside of the VM failed.
+    Io(String)
+}
+
+impl fmt::Display for VmErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            VmErrorKind::UnknownMemory(id) => write!(f, "unknown memory {} referenced", id),
+            VmErrorKind::TypeMismatch(msg) => write!(f, "type mismatch: {}", msg),
+            VmErrorKind::DivisionByZero => write!(f, "division by zero"),
+            VmErrorKind::Overflow(op) => write!(f, "integer overflow in {}", op),
+            VmErrorKind::IndexOutOfBounds(index, len) => write!(f, "index {} out of bounds for list of length {}", index, len),
+            VmErrorKind::MissingBlock(id) => write!(f, "jump to undefined block {}", id),
+            VmErrorKind::MissingFunction(id) => write!(f, "call to undefined function {}", id),
+            VmErrorKind::StackOverflow(depth) => write!(f, "stack overflow: calls nested deeper than {}", depth),
+            VmErrorKind::MalformedInstruction(msg) => write!(f, "malformed instruction: {}", msg),
+            VmErrorKind::Domain(msg) => write!(f, "math domain error: {}", msg),
+            VmErrorKind::Io(msg) => write!(f, "io error: {}", msg),
+        };
+    }
+}
+
+/// A failure raised while executing bytecode.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VmError {
+    pub kind:VmErrorKind,
+    /// Position of the failing instruction's opcode in the bytecode.
+    pub offset:usize,
+    pub opcode:u32
+}
+
+impl fmt::Display for VmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match bc::mnemonic(self.opcode) {
+            Some(name) => name.to_string(),
+            None => format!("{:#x}", self.opcode),
+        };
+        return write!(f, "runtime error in {} at offset {}: {}", name, self.offset, self.kind);
+    }
+}
+
+impl std::error::Error for VmError {}
@@ -0,0 +1,544 @@
+use std::collections::HashMap;
+
+use super::bytecodes::{self as bc, schema, ByteCode, ByteType, Constant};
+use super::error::{VmError, VmErrorKind};
+
+/// The math intrinsics that take a single number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathOp {
+    Neg,
+    Abs,
+    Floor,
+    Ceil,
+    Round,
+    Sqrt,
+    Sin,
+    Cos,
+    Log
+}
+
+impl MathOp {
+    pub fn from_opcode(opcode:u32) -> Option<MathOp> {
+        return Some(match opcode {
+            bc::NEG => MathOp::Neg,
+            bc::ABS => MathOp::Abs,
+            bc::FLOOR => MathOp::Floor,
+            bc::CEIL => MathOp::Ceil,
+            bc::ROUND => MathOp::Round,
+            bc::SQRT => MathOp::Sqrt,
+            bc::SIN => MathOp::Sin,
+            bc::COS => MathOp::Cos,
+            bc::LOG => MathOp::Log,
+            _ => return None
+        });
+    }
+
+    pub fn opcode(&self) -> u32 {
+        return match self {
+            MathOp::Neg => bc::NEG,
+            MathOp::Abs => bc::ABS,
+            MathOp::Floor => bc::FLOOR,
+            MathOp::Ceil => bc::CEIL,
+            MathOp::Round => bc::ROUND,
+            MathOp::Sqrt => bc::SQRT,
+            MathOp::Sin => bc::SIN,
+            MathOp::Cos => bc::COS,
+            MathOp::Log => bc::LOG,
+        };
+    }
+}
+
+/// An instruction with its operands decoded, so the executor never re-reads raw words.
+///
+/// Variables are referred to by the slot `Resolver` gave their id, constants are copied out of
+/// the pool and jump and call targets are indices into the decoded instruction list.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Instr {
+    Start,
+    Alloca(usize),
+    /// slot, value
+    Store(usize, usize),
+    Del(usize),
+    /// The binary operators all take `cid lhs rhs`.
+    Eq(usize, usize, usize),
+    Neq(usize, usize, usize),
+    Gt(usize, usize, usize),
+    Gte(usize, usize, usize),
+    Lt(usize, usize, usize),
+    Lte(usize, usize, usize),
+    Add(usize, usize, usize),
+    Sub(usize, usize, usize),
+    Mul(usize, usize, usize),
+    Div(usize, usize, usize),
+    Mod(usize, usize, usize),
+    Exp(usize, usize, usize),
+    And(usize, usize, usize),
+    Or(usize, usize, usize),
+    Band(usize, usize, usize),
+    Bor(usize, usize, usize),
+    Bxor(usize, usize, usize),
+    Shl(usize, usize, usize),
+    Shr(usize, usize, usize),
+    Min(usize, usize, usize),
+    Max(usize, usize, usize),
+    /// cid, value
+    Not(usize, usize),
+    /// One of the single operand math intrinsics: cid, value
+    Math(MathOp, usize, usize),
+    /// A STR, NUM or INT literal: cid, value
+    Constant(usize, Constant),
+    Bool(usize, bool),
+    /// cid, format string, arguments
+    Fmt(usize, usize, Vec<usize>),
+    Stdout(usize),
+    Stdin(usize),
+    BeginScope,
+    EndScope,
+    /// The block id.
+    Block(u32),
+    /// block id, the instruction after the BLOCK or `None` if no such block exists
+    Jump(u32, Option<usize>),
+    /// block id, condition, target
+    CondJump(u32, usize, Option<usize>),
+    CastStr(usize, usize),
+    CastNum(usize, usize),
+    CastInt(usize, usize),
+    CastFloat(usize, usize),
+    /// cid, number, precision
+    FmtNum(usize, usize, usize),
+    /// cid, path, mode
+    Open(usize, usize, usize),
+    Close(usize),
+    /// cid, file
+    Read(usize, usize),
+    /// file, value
+    Write(usize, usize),
+    /// cid, items
+    List(usize, Vec<usize>),
+    /// cid, list, indices
+    Index(usize, usize, Vec<usize>),
+    /// list, item, indices
+    StoreIndex(usize, usize, Vec<usize>),
+    /// list, item
+    Push(usize, usize),
+    /// cid, list
+    Pop(usize, usize),
+    /// An index into `Decoded.functions`; execution that falls into a function skips its body.
+    Func(usize),
+    /// cid, function id, index into `Decoded.functions` or `None` if it is undefined, arguments
+    Call(usize, u32, Option<usize>, Vec<usize>),
+    Ret(Option<usize>),
+    End
+}
+
+impl Instr {
+    pub fn opcode(&self) -> u32 {
+        return match self {
+            Instr::Start => bc::START,
+            Instr::Alloca(_) => bc::ALLOCA,
+            Instr::Store(..) => bc::STORE,
+            Instr::Del(_) => bc::DEL,
+            Instr::Eq(..) => bc::EQ,
+            Instr::Neq(..) => bc::NEQ,
+            Instr::Gt(..) => bc::GT,
+            Instr::Gte(..) => bc::GTE,
+            Instr::Lt(..) => bc::LT,
+            Instr::Lte(..) => bc::LTE,
+            Instr::Add(..) => bc::ADD,
+            Instr::Sub(..) => bc::SUB,
+            Instr::Mul(..) => bc::MUL,
+            Instr::Div(..) => bc::DIV,
+            Instr::Mod(..) => bc::MOD,
+            Instr::Exp(..) => bc::EXP,
+            Instr::And(..) => bc::AND,
+            Instr::Or(..) => bc::OR,
+            Instr::Band(..) => bc::BAND,
+            Instr::Bor(..) => bc::BOR,
+            Instr::Bxor(..) => bc::BXOR,
+            Instr::Shl(..) => bc::SHL,
+            Instr::Shr(..) => bc::SHR,
+            Instr::Min(..) => bc::MIN,
+            Instr::Max(..) => bc::MAX,
+            Instr::Not(..) => bc::NOT,
+            Instr::Math(op, ..) => op.opcode(),
+            Instr::Constant(_, Constant::Int(_)) => bc::INT,
+            Instr::Constant(_, Constant::Float(_)) => bc::NUM,
+            Instr::Constant(_, Constant::Str(_)) => bc::STR,
+            Instr::Bool(..) => bc::BOOL,
+            Instr::Fmt(..) => bc::FMT,
+            Instr::Stdout(_) => bc::STDOUT,
+            Instr::Stdin(_) => bc::STDIN,
+            Instr::BeginScope => bc::BEGIN_SCOPE,
+            Instr::EndScope => bc::END_SCOPE,
+            Instr::Block(_) => bc::BLOCK,
+            Instr::Jump(..) => bc::JUMP,
+            Instr::CondJump(..) => bc::COND_JUMP,
+            Instr::CastStr(..) => bc::CAST_STR,
+            Instr::CastNum(..) => bc::CAST_NUM,
+            Instr::CastInt(..) => bc::CAST_INT,
+            Instr::CastFloat(..) => bc::CAST_FLOAT,
+            Instr::FmtNum(..) => bc::FMT_NUM,
+            Instr::Open(..) => bc::OPEN,
+            Instr::Close(_) => bc::CLOSE,
+            Instr::Read(..) => bc::READ,
+            Instr::Write(..) => bc::WRITE,
+            Instr::List(..) => bc::LIST,
+            Instr::Index(..) => bc::INDEX,
+            Instr::StoreIndex(..) => bc::STORE_INDEX,
+            Instr::Push(..) => bc::PUSH,
+            Instr::Pop(..) => bc::POP,
+            Instr::Func(_) => bc::FUNC,
+            Instr::Call(..) => bc::CALL,
+            Instr::Ret(_) => bc::RET,
+            Instr::End => bc::END,
+        };
+    }
+
+    /// The slots whose values this instruction reads.
+    pub fn reads(&self) -> Vec<usize> {
+        return match self {
+            Instr::Store(_, val) | Instr::CastStr(_, val) | Instr::CastNum(_, val) | Instr::CastInt(_, val)
+            | Instr::CastFloat(_, val) | Instr::Read(_, val) | Instr::Pop(_, val) | Instr::Not(_, val)
+            | Instr::Math(_, _, val) => vec![*val],
+            Instr::Eq(_, lhs, rhs) | Instr::Neq(_, lhs, rhs) | Instr::Gt(_, lhs, rhs) | Instr::Gte(_, lhs, rhs)
+            | Instr::Lt(_, lhs, rhs) | Instr::Lte(_, lhs, rhs) | Instr::Add(_, lhs, rhs) | Instr::Sub(_, lhs, rhs)
+            | Instr::Mul(_, lhs, rhs) | Instr::Div(_, lhs, rhs) | Instr::Mod(_, lhs, rhs) | Instr::Exp(_, lhs, rhs)
+            | Instr::And(_, lhs, rhs) | Instr::Or(_, lhs, rhs) | Instr::Band(_, lhs, rhs) | Instr::Bor(_, lhs, rhs)
+            | Instr::Bxor(_, lhs, rhs) | Instr::Shl(_, lhs, rhs) | Instr::Shr(_, lhs, rhs) | Instr::Min(_, lhs, rhs)
+            | Instr::Max(_, lhs, rhs) | Instr::FmtNum(_, lhs, rhs) | Instr::Open(_, lhs, rhs) => vec![*lhs, *rhs],
+            Instr::Del(id) | Instr::Stdout(id) | Instr::CondJump(_, id, _) | Instr::Close(id) => vec![*id],
+            Instr::Write(file, val) | Instr::Push(file, val) => vec![*file, *val],
+            Instr::Fmt(_, first, rest) | Instr::Index(_, first, rest) => [&[*first], rest.as_slice()].concat(),
+            Instr::StoreIndex(list, item, indices) => [&[*list, *item], indices.as_slice()].concat(),
+            Instr::List(_, items) | Instr::Call(_, _, _, items) => items.clone(),
+            Instr::Ret(value) => value.iter().copied().collect(),
+            _ => vec![],
+        };
+    }
+
+    /// The slot this instruction assigns, if any.
+    pub fn writes(&self) -> Option<usize> {
+        return match self {
+            Instr::Alloca(cid) | Instr::Store(cid, _) | Instr::Eq(cid, ..) | Instr::Neq(cid, ..) | Instr::Gt(cid, ..)
+            | Instr::Gte(cid, ..) | Instr::Lt(cid, ..) | Instr::Lte(cid, ..) | Instr::Add(cid, ..) | Instr::Sub(cid, ..)
+            | Instr::Mul(cid, ..) | Instr::Div(cid, ..) | Instr::Mod(cid, ..) | Instr::Exp(cid, ..) | Instr::And(cid, ..)
+            | Instr::Or(cid, ..) | Instr::Band(cid, ..) | Instr::Bor(cid, ..) | Instr::Bxor(cid, ..) | Instr::Shl(cid, ..)
+            | Instr::Shr(cid, ..) | Instr::Min(cid, ..) | Instr::Max(cid, ..) | Instr::Not(cid, _) | Instr::Math(_, cid, _)
+            | Instr::Constant(cid, _)
+            | Instr::Bool(cid, _) | Instr::Fmt(cid, ..) | Instr::Stdin(cid) | Instr::CastStr(cid, _) | Instr::CastNum(cid, _)
+            | Instr::CastInt(cid, _) | Instr::CastFloat(cid, _) | Instr::FmtNum(cid, ..) | Instr::Open(cid, ..)
+            | Instr::Read(cid, _) | Instr::List(cid, _) | Instr::Index(cid, ..) | Instr::Pop(cid, _)
+            | Instr::Call(cid, ..) => Some(*cid),
+            _ => None,
+        };
+    }
+}
+
+/// Assigns every variable id a slot, numbering them in the order they are first seen.
+///
+/// The executor keeps one resolver for its lifetime so ids keep their slots across runs.
+#[derive(Clone, Debug, Default)]
+pub struct Resolver {
+    slots:HashMap<u32, usize>,
+    /// slot, id
+    ids:Vec<u32>
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        return Resolver::default();
+    }
+
+    /// The slot of `id`, assigning the next free one if it has none yet.
+    pub fn resolve(&mut self, id:u32) -> usize {
+        if let Some(slot) = self.slots.get(&id) {
+            return *slot;
+        }
+        self.ids.push(id);
+        self.slots.insert(id, self.ids.len() - 1);
+        return self.ids.len() - 1;
+    }
+
+    /// The slot of `id`, if it has been given one.
+    pub fn slot(&self, id:u32) -> Option<usize> {
+        return self.slots.get(&id).copied();
+    }
+
+    /// The id a slot was assigned to.
+    pub fn id(&self, slot:usize) -> u32 {
+        return self.ids[slot];
+    }
+
+    pub fn len(&self) -> usize {
+        return self.ids.len();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.ids.is_empty();
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    pub id:u32,
+    pub params:Vec<usize>,
+    /// The first instruction of the body.
+    pub body:usize,
+    /// The matching END.
+    pub end:usize
+}
+
+/// A program decoded by `decode`.
+#[derive(Clone, Debug, Default)]
+pub struct Decoded {
+    pub instrs:Vec<Instr>,
+    /// The offset and opcode each instruction was decoded from, for error reports.
+    pub origins:Vec<(usize, u32)>,
+    pub functions:Vec<Function>
+}
+
+/// Decodes `bytecode` into a list of instructions, checking that every instruction is well formed
+/// and that FUNC and END pair up. Variable ids are given slots by `resolver`.
+pub fn decode(bytecode:&ByteCode, resolver:&mut Resolver) -> Result<Decoded, VmError> {
+    let mut decoded = Decoded::default();
+    // block id, instruction after the BLOCK
+    let mut blocks:HashMap<u32, usize> = HashMap::new();
+    // function id, index into `decoded.functions`
+    let mut functions:HashMap<u32, usize> = HashMap::new();
+    // The FUNC whose END has not been seen yet, and where its header starts.
+    let mut open_function:Option<(usize, usize)> = None;
+    let mut pos = 0;
+    while pos < bytecode.len() {
+        let opcode = bytecode.at(pos);
+        let malformed = |msg:String| VmError { kind: VmErrorKind::MalformedInstruction(msg), offset: pos, opcode: opcode };
+        let len = bytecode.instruction_len(pos).map_err(malformed)?;
+        let endl = if schema(opcode).is_some_and(|schema| schema.endl) {1} else {0};
+        let operands:Vec<u32> = (pos + 1..pos + len - endl).map(|word| bytecode.at(word)).collect();
+        let count = operands.len();
+        let mut slot = |operand:usize| resolver.resolve(operands[operand]);
+        let instr = match opcode {
+            bc::START => Instr::Start,
+            bc::ALLOCA => Instr::Alloca(slot(0)),
+            bc::STORE => Instr::Store(slot(0), slot(1)),
+            bc::DEL => Instr::Del(slot(0)),
+            bc::EQ => Instr::Eq(slot(0), slot(1), slot(2)),
+            bc::NEQ => Instr::Neq(slot(0), slot(1), slot(2)),
+            bc::GT => Instr::Gt(slot(0), slot(1), slot(2)),
+            bc::GTE => Instr::Gte(slot(0), slot(1), slot(2)),
+            bc::LT => Instr::Lt(slot(0), slot(1), slot(2)),
+            bc::LTE => Instr::Lte(slot(0), slot(1), slot(2)),
+            bc::ADD => Instr::Add(slot(0), slot(1), slot(2)),
+            bc::SUB => Instr::Sub(slot(0), slot(1), slot(2)),
+            bc::MUL => Instr::Mul(slot(0), slot(1), slot(2)),
+            bc::DIV => Instr::Div(slot(0), slot(1), slot(2)),
+            bc::MOD => Instr::Mod(slot(0), slot(1), slot(2)),
+            bc::EXP => Instr::Exp(slot(0), slot(1), slot(2)),
+            bc::AND => Instr::And(slot(0), slot(1), slot(2)),
+            bc::OR => Instr::Or(slot(0), slot(1), slot(2)),
+            bc::NOT => Instr::Not(slot(0), slot(1)),
+            bc::BAND => Instr::Band(slot(0), slot(1), slot(2)),
+            bc::BOR => Instr::Bor(slot(0), slot(1), slot(2)),
+            bc::BXOR => Instr::Bxor(slot(0), slot(1), slot(2)),
+            bc::SHL => Instr::Shl(slot(0), slot(1), slot(2)),
+            bc::SHR => Instr::Shr(slot(0), slot(1), slot(2)),
+            bc::MIN => Instr::Min(slot(0), slot(1), slot(2)),
+            bc::MAX => Instr::Max(slot(0), slot(1), slot(2)),
+            bc::NEG | bc::ABS | bc::FLOOR | bc::CEIL | bc::ROUND | bc::SQRT | bc::SIN | bc::COS | bc::LOG => {
+                let op = MathOp::from_opcode(opcode).unwrap();
+                Instr::Math(op, slot(0), slot(1))
+            },
+            bc::STR | bc::NUM | bc::INT => {
+                let constant = bytecode.constant(operands[1])
+                    .ok_or_else(|| malformed(format!("constant {} is not in the constant pool", operands[1])))?;
+                Instr::Constant(slot(0), constant.clone())
+            },
+            bc::BOOL => Instr::Bool(slot(0), operands[1] == 1),
+            bc::FMT => Instr::Fmt(slot(0), slot(1), (2..count).map(&mut slot).collect()),
+            bc::STDOUT => Instr::Stdout(slot(0)),
+            bc::STDIN => Instr::Stdin(slot(0)),
+            bc::BEGIN_SCOPE => Instr::BeginScope,
+            bc::END_SCOPE => Instr::EndScope,
+            bc::BLOCK => {
+                blocks.insert(operands[0], decoded.instrs.len() + 1);
+                Instr::Block(operands[0])
+            },
+            // Targets are filled in once every block has been seen.
+            bc::JUMP => Instr::Jump(operands[0], None),
+            bc::COND_JUMP => Instr::CondJump(operands[0], slot(1), None),
+            bc::CAST_STR => Instr::CastStr(slot(0), slot(1)),
+            bc::CAST_NUM => Instr::CastNum(slot(0), slot(1)),
+            bc::CAST_INT => Instr::CastInt(slot(0), slot(1)),
+            bc::CAST_FLOAT => Instr::CastFloat(slot(0), slot(1)),
+            bc::FMT_NUM => Instr::FmtNum(slot(0), slot(1), slot(2)),
+            bc::OPEN => Instr::Open(slot(0), slot(1), slot(2)),
+            bc::CLOSE => Instr::Close(slot(0)),
+            bc::READ => Instr::Read(slot(0), slot(1)),
+            bc::WRITE => Instr::Write(slot(0), slot(1)),
+            bc::LIST => Instr::List(slot(0), (1..count).map(&mut slot).collect()),
+            bc::INDEX => Instr::Index(slot(0), slot(1), (2..count).map(&mut slot).collect()),
+            bc::STORE_INDEX => Instr::StoreIndex(slot(0), slot(1), (2..count).map(&mut slot).collect()),
+            bc::PUSH => Instr::Push(slot(0), slot(1)),
+            bc::POP => Instr::Pop(slot(0), slot(1)),
+            bc::FUNC => {
+                if open_function.is_some() {
+                    return Err(malformed(String::from("FUNC cannot be nested")));
+                }
+                let index = decoded.functions.len();
+                let params = (1..count).map(&mut slot).collect();
+                decoded.functions.push(Function { id: operands[0], params: params, body: decoded.instrs.len() + 1, end: 0 });
+                functions.insert(operands[0], index);
+                open_function = Some((index, pos));
+                Instr::Func(index)
+            },
+            bc::CALL => Instr::Call(slot(0), operands[1], None, (2..count).map(&mut slot).collect()),
+            bc::RET if count > 1 => return Err(malformed(String::from("RET takes at most one value"))),
+            bc::RET => Instr::Ret(if count == 1 {Some(slot(0))} else {None}),
+            bc::END => {
+                let Some((index, _)) = open_function.take() else {
+                    return Err(malformed(String::from("END without a matching FUNC")));
+                };
+                decoded.functions[index].end = decoded.instrs.len();
+                Instr::End
+            },
+            _ => return Err(malformed(format!("Unknown opcode {:#x} at offset {}.", opcode, pos))),
+        };
+        decoded.instrs.push(instr);
+        decoded.origins.push((pos, opcode));
+        pos += len;
+    }
+    if let Some((_, offset)) = open_function {
+        let kind = VmErrorKind::MalformedInstruction(String::from("FUNC has no matching END"));
+        return Err(VmError { kind: kind, offset: offset, opcode: bc::FUNC });
+    }
+
+    for instr in decoded.instrs.iter_mut() {
+        match instr {
+            Instr::Jump(block, target) | Instr::CondJump(block, _, target) => *target = blocks.get(block).copied(),
+            Instr::Call(_, func, target, _) => *target = functions.get(func).copied(),
+            _ => {}
+        }
+    }
+    return Ok(decoded);
+}
+
+/// Writes instructions back out as bytecode, the inverse of `decode`. `functions` and `resolver`
+/// must be the ones the instructions were decoded with.
+pub fn encode(instrs:&[Instr], functions:&[Function], resolver:&Resolver, into:&mut ByteCode) {
+    for instr in instrs {
+        let ids = |slots:&[usize]| slots.iter().map(|slot| resolver.id(*slot)).collect::<Vec<u32>>();
+        let operands = match instr {
+            Instr::Start | Instr::BeginScope | Instr::EndScope | Instr::End => vec![],
+            Instr::Alloca(slot) | Instr::Del(slot) | Instr::Stdout(slot) | Instr::Stdin(slot) | Instr::Close(slot) => ids(&[*slot]),
+            Instr::Store(lhs, rhs) | Instr::CastStr(lhs, rhs) | Instr::CastNum(lhs, rhs) | Instr::CastInt(lhs, rhs)
+            | Instr::CastFloat(lhs, rhs) | Instr::Read(lhs, rhs) | Instr::Write(lhs, rhs) | Instr::Push(lhs, rhs)
+            | Instr::Pop(lhs, rhs) | Instr::Not(lhs, rhs) | Instr::Math(_, lhs, rhs) => ids(&[*lhs, *rhs]),
+            Instr::Eq(cid, lhs, rhs) | Instr::Neq(cid, lhs, rhs) | Instr::Gt(cid, lhs, rhs) | Instr::Gte(cid, lhs, rhs)
+            | Instr::Lt(cid, lhs, rhs) | Instr::Lte(cid, lhs, rhs) | Instr::Add(cid, lhs, rhs) | Instr::Sub(cid, lhs, rhs)
+            | Instr::Mul(cid, lhs, rhs) | Instr::Div(cid, lhs, rhs) | Instr::Mod(cid, lhs, rhs) | Instr::Exp(cid, lhs, rhs)
+            | Instr::And(cid, lhs, rhs) | Instr::Or(cid, lhs, rhs) | Instr::Band(cid, lhs, rhs) | Instr::Bor(cid, lhs, rhs)
+            | Instr::Bxor(cid, lhs, rhs) | Instr::Shl(cid, lhs, rhs) | Instr::Shr(cid, lhs, rhs) | Instr::Min(cid, lhs, rhs)
+            | Instr::Max(cid, lhs, rhs) | Instr::FmtNum(cid, lhs, rhs) | Instr::Open(cid, lhs, rhs) => ids(&[*cid, *lhs, *rhs]),
+            Instr::Constant(cid, constant) => vec![resolver.id(*cid), into.add_constant(constant.clone())],
+            Instr::Bool(cid, val) => vec![resolver.id(*cid), *val as u32],
+            Instr::Fmt(cid, first, rest) | Instr::Index(cid, first, rest) | Instr::StoreIndex(cid, first, rest) => {
+                [ids(&[*cid, *first]), ids(rest)].concat()
+            },
+            Instr::List(cid, items) => [ids(&[*cid]), ids(items)].concat(),
+            Instr::Block(block) | Instr::Jump(block, _) => vec![*block],
+            Instr::CondJump(block, cond, _) => vec![*block, resolver.id(*cond)],
+            Instr::Func(func) => [vec![functions[*func].id], ids(&functions[*func].params)].concat(),
+            Instr::Call(cid, func, _, args) => [vec![resolver.id(*cid), *func], ids(args)].concat(),
+            Instr::Ret(value) => value.iter().map(|slot| resolver.id(*slot)).collect(),
+        };
+        let opcode = instr.opcode();
+        into.append(ByteType::Num(opcode));
+        for word in operands {
+            into.append(ByteType::Num(word));
+        }
+        if schema(opcode).is_some_and(|schema| schema.endl) {
+            into.append(ByteType::Num(bc::ENDL));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::vm::bytecodes::{self as bc, Constant};
+    use crate::lexer::disasm::Disassembler;
+    use crate::vm::instr::{decode, encode, Function, Instr, Resolver};
+
+    #[test]
+    fn instr_test_resolves_targets() {
+        let src = "
+            NUM one 1
+            BLOCK again
+            FUNC inc value
+                ADD out value one
+                RET out
+            END
+            START
+                CALL two inc one
+                JUMP again
+        ";
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let mut resolver = Resolver::new();
+        let decoded = decode(&program.bytecode, &mut resolver).unwrap();
+        let sym = |name:&str| program.symbols[name];
+        // Slots are handed out in the order variables first appear.
+        let slot = |name:&str| resolver.slot(sym(name)).unwrap();
+        assert_eq!((slot("one"), slot("value"), slot("out"), slot("two")), (0, 1, 2, 3));
+        assert_eq!(resolver.slot(sym("again")), None);
+        assert_eq!(decoded.instrs, vec![
+            Instr::Constant(slot("one"), Constant::Float(1.0)),
+            Instr::Block(sym("again")),
+            Instr::Func(0),
+            Instr::Add(slot("out"), slot("value"), slot("one")),
+            Instr::Ret(Some(slot("out"))),
+            Instr::End,
+            Instr::Start,
+            Instr::Call(slot("two"), sym("inc"), Some(0), vec![slot("one")]),
+            Instr::Jump(sym("again"), Some(2)),
+        ]);
+        assert_eq!(decoded.functions, vec![Function { id: sym("inc"), params: vec![slot("value")], body: 3, end: 5 }]);
+        assert_eq!(decoded.origins[2], (7, bc::FUNC));
+    }
+
+    #[test]
+    fn instr_test_encode_round_trip() {
+        let src = "
+            NUM one 1
+            STR text \"{} {}\"
+            BLOCK again
+            FUNC inc value
+                ADD out value one
+                RET out
+            END
+            START
+                INT two 2
+                BOOL yes true
+                CALL three inc two
+                FMT line text three yes
+                LIST items one two
+                INDEX first items one
+                STORE_INDEX items line one
+                COND_JUMP yes again
+                RET
+        ";
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let mut resolver = Resolver::new();
+        let decoded = decode(&program.bytecode, &mut resolver).unwrap();
+        let mut encoded = program.bytecode.empty_like();
+        encode(&decoded.instrs, &decoded.functions, &resolver, &mut encoded);
+        assert_eq!(decode(&encoded, &mut resolver).unwrap().instrs, decoded.instrs);
+        assert_eq!(Disassembler::new(&encoded).disassemble(), Disassembler::new(&program.bytecode).disassemble());
+    }
+
+    #[test]
+    fn instr_test_unresolved_targets() {
+        let mut bb = bc::BytecodeBuilder::new();
+        bb.write_start();
+        bb.write_jump(0x999);
+        assert_eq!(decode(&bb.src, &mut Resolver::new()).unwrap().instrs[1], Instr::Jump(0x999, None));
+    }
+}
@@ -0,0 +1,105 @@
+use std::{cell::RefCell, io::{self, BufRead, Write}, rc::Rc};
+
+/// Where STDOUT writes to and STDIN reads from, so a host can capture output or script input.
+pub trait Io {
+    fn write(&mut self, bytes:&[u8]) -> io::Result<()>;
+    /// Reads the next line including its line ending, or an empty string once input runs out.
+    fn read_line(&mut self) -> io::Result<String>;
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// The process's standard output and input, used by `Executor` unless told otherwise.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct StdIo;
+
+impl Io for StdIo {
+    fn write(&mut self, bytes:&[u8]) -> io::Result<()> {
+        return io::stdout().write_all(bytes);
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut line = String::new();
+        io::stdin().lock().read_line(&mut line)?;
+        return Ok(line);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return io::stdout().flush();
+    }
+}
+
+#[derive(Debug, Default)]
+struct Buffers {
+    /// Input not read yet.
+    input:String,
+    output:Vec<u8>
+}
+
+/// Keeps output in memory and serves input from a string.
+///
+/// Clones share the same buffers, so a clone kept by the caller sees what a program run with
+/// another clone printed.
+#[derive(Clone, Debug, Default)]
+pub struct BufferIo {
+    buffers:Rc<RefCell<Buffers>>
+}
+
+impl BufferIo {
+    pub fn new() -> BufferIo {
+        return BufferIo::default();
+    }
+
+    /// A buffer whose STDIN reads the lines of `input`.
+    pub fn with_input(input:&str) -> BufferIo {
+        let io = BufferIo::new();
+        io.buffers.borrow_mut().input = String::from(input);
+        return io;
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced.
+    pub fn output(&self) -> String {
+        return String::from_utf8_lossy(&self.buffers.borrow().output).into_owned();
+    }
+
+    /// Returns everything written so far and empties the output.
+    pub fn take_output(&self) -> String {
+        let output = std::mem::take(&mut self.buffers.borrow_mut().output);
+        return String::from_utf8_lossy(&output).into_owned();
+    }
+}
+
+impl Io for BufferIo {
+    fn write(&mut self, bytes:&[u8]) -> io::Result<()> {
+        self.buffers.borrow_mut().output.extend_from_slice(bytes);
+        return Ok(());
+    }
+
+    fn read_line(&mut self) -> io::Result<String> {
+        let mut buffers = self.buffers.borrow_mut();
+        let end = buffers.input.find('\n').map_or(buffers.input.len(), |newline| newline + 1);
+        return Ok(buffers.input.drain(..end).collect());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vm::io::{BufferIo, Io};
+
+    #[test]
+    fn io_test_buffer() {
+        let io = BufferIo::with_input("first\nsecond");
+        let mut handle = io.clone();
+        assert_eq!(handle.read_line().unwrap(), "first\n");
+        assert_eq!(handle.read_line().unwrap(), "second");
+        assert_eq!(handle.read_line().unwrap(), "");
+        handle.write("héllo ".as_bytes()).unwrap();
+        handle.write(b"world").unwrap();
+        assert_eq!(io.output(), "héllo world");
+        assert_eq!(io.take_output(), "héllo world");
+        assert_eq!(io.output(), "");
+    }
+}
@@ -1,2 +1,10 @@
+#[allow(clippy::module_inception)]
 pub mod vm;
-pub mod bytecodes;
\ No newline at end of file
+pub mod bytecodes;
+pub mod error;
+pub mod io;
+pub mod instr;
+pub mod verify;
+pub mod cfg;
+pub mod opt;
+pub mod debug;
\ No newline at end of file
@@ -0,0 +1,574 @@
+use std::collections::{HashMap, HashSet};
+
+use super::bytecodes::ByteCode;
+use super::cfg::{Cfg, EdgeKind};
+use super::error::VmError;
+use super::instr::{encode, Instr};
+use super::vm::fold;
+
+/// The highest level `optimize` understands. Higher levels are treated as this one.
+pub const MAX_LEVEL:u8 = 2;
+
+/// How many times the passes are repeated while they keep finding work.
+const MAX_ROUNDS:usize = 8;
+
+/// A pass rewrites the instructions of `cfg.decoded`, replacing the ones it removes with `None`,
+/// and reports whether it changed anything.
+type Pass = fn(&Cfg, &mut [Option<Instr>]) -> bool;
+
+/// Rewrites a program into one that prints the same output and fails with the same errors, but
+/// does less work on the way.
+///
+/// Level 0 only copies the program. Level 1 folds constant expressions and threads jumps, and
+/// level 2 also removes dead stores and unreachable code.
+pub fn optimize(bytecode:&ByteCode, level:u8) -> Result<ByteCode, VmError> {
+    let mut passes:Vec<Pass> = vec![];
+    if level >= 1 {
+        passes.push(fold_constants);
+        passes.push(thread_jumps);
+    }
+    if level >= 2 {
+        passes.push(remove_dead_stores);
+        passes.push(remove_unreachable);
+    }
+
+    let mut current = rewrite(bytecode, |_, _| false)?.0;
+    for _ in 0..MAX_ROUNDS {
+        let mut changed = false;
+        for pass in &passes {
+            let (next, progress) = rewrite(&current, pass)?;
+            current = next;
+            changed |= progress;
+        }
+        if !changed {
+            break
+        }
+    }
+    return Ok(current);
+}
+
+/// Runs a pass over `bytecode` and encodes the result.
+fn rewrite(bytecode:&ByteCode, pass:impl FnOnce(&Cfg, &mut [Option<Instr>]) -> bool) -> Result<(ByteCode, bool), VmError> {
+    let cfg = Cfg::new(bytecode)?;
+    let mut instrs:Vec<Option<Instr>> = cfg.decoded.instrs.iter().cloned().map(Some).collect();
+    let changed = pass(&cfg, &mut instrs);
+    let instrs:Vec<Instr> = instrs.into_iter().flatten().collect();
+    let mut out = bytecode.empty_like();
+    encode(&instrs, &cfg.decoded.functions, &cfg.resolver, &mut out);
+    return Ok((out, changed));
+}
+
+/// Replaces operators and STOREs whose operands are known literals with the literal they
+/// produce, and COND_JUMPs on a known BOOL with a JUMP or nothing.
+///
+/// Values are only tracked within a basic block, apart from variables the prelude assigns once
+/// and nothing else ever writes or deletes. The prelude itself is left alone, since before START
+/// only its literals run.
+fn fold_constants(cfg:&Cfg, instrs:&mut [Option<Instr>]) -> bool {
+    let globals = globals(cfg);
+    let mut changed = false;
+    for block in cfg.blocks.iter().filter(|block| !block.prelude) {
+        let mut known:HashMap<usize, Instr> = HashMap::new();
+        for index in block.instrs.clone() {
+            let Some(instr) = &instrs[index] else {
+                continue
+            };
+            let value = |slot:&usize| known.get(slot).or_else(|| globals.get(slot));
+            let replacement = match instr {
+                Instr::Eq(_, lhs, rhs) | Instr::Neq(_, lhs, rhs) | Instr::Gt(_, lhs, rhs) | Instr::Gte(_, lhs, rhs)
+                | Instr::Lt(_, lhs, rhs) | Instr::Lte(_, lhs, rhs) | Instr::Add(_, lhs, rhs) | Instr::Sub(_, lhs, rhs)
+                | Instr::Mul(_, lhs, rhs) | Instr::Div(_, lhs, rhs) | Instr::Mod(_, lhs, rhs) | Instr::Exp(_, lhs, rhs)
+                | Instr::And(_, lhs, rhs) | Instr::Or(_, lhs, rhs) | Instr::Band(_, lhs, rhs) | Instr::Bor(_, lhs, rhs)
+                | Instr::Bxor(_, lhs, rhs) | Instr::Shl(_, lhs, rhs) | Instr::Shr(_, lhs, rhs) | Instr::Min(_, lhs, rhs)
+                | Instr::Max(_, lhs, rhs) => {
+                    match (value(lhs), value(rhs)) {
+                        (Some(lhs), Some(rhs)) => fold(instr, &[lhs, rhs]).map(Some),
+                        _ => None,
+                    }
+                },
+                Instr::Not(_, val) | Instr::Math(_, _, val) => value(val).and_then(|val| fold(instr, &[val])).map(Some),
+                Instr::Store(cid, val) => value(val).map(|val| Some(relabel(val, *cid))),
+                Instr::CondJump(block, cond, target) => match value(cond) {
+                    Some(Instr::Bool(_, true)) => Some(Some(Instr::Jump(*block, *target))),
+                    Some(Instr::Bool(_, false)) => Some(None),
+                    _ => None,
+                },
+                _ => None,
+            };
+            if let Some(replacement) = replacement {
+                instrs[index] = replacement;
+                changed = true;
+            }
+
+            match &instrs[index] {
+                // Scopes and calls can uncover older bindings, so start over.
+                Some(Instr::EndScope | Instr::Del(_) | Instr::Call(..)) => known.clear(),
+                Some(instr @ (Instr::Constant(cid, _) | Instr::Bool(cid, _))) => {
+                    known.insert(*cid, instr.clone());
+                },
+                Some(instr) => if let Some(cid) = instr.writes() {
+                    known.remove(&cid);
+                },
+                None => {},
+            }
+        }
+    }
+    return changed;
+}
+
+/// A literal instruction loading the same value into `cid`.
+fn relabel(literal:&Instr, cid:usize) -> Instr {
+    return match literal {
+        Instr::Constant(_, constant) => Instr::Constant(cid, constant.clone()),
+        Instr::Bool(_, val) => Instr::Bool(cid, *val),
+        _ => unreachable!("only literals are tracked"),
+    };
+}
+
+/// Variables that hold the same literal from START onwards: assigned by a literal in the prelude,
+/// never written, deleted or bound as a parameter anywhere else, and never dropped by an
+/// END_SCOPE that could reach the global scope.
+fn globals(cfg:&Cfg) -> HashMap<usize, Instr> {
+    let instrs = &cfg.decoded.instrs;
+    if cfg.start.is_none() || may_leave_global_scope(cfg) {
+        return HashMap::new();
+    }
+    let mut writes:HashMap<usize, usize> = HashMap::new();
+    for instr in instrs {
+        if let Some(slot) = instr.writes().or(if let Instr::Del(slot) = instr {Some(*slot)} else {None}) {
+            *writes.entry(slot).or_default() += 1;
+        }
+    }
+    for function in &cfg.decoded.functions {
+        for param in &function.params {
+            *writes.entry(*param).or_default() += 1;
+        }
+    }
+
+    let mut globals = HashMap::new();
+    for block in cfg.blocks.iter().filter(|block| block.prelude) {
+        for instr in &instrs[block.instrs.clone()] {
+            if let Instr::Constant(slot, _) = instr {
+                if writes[slot] == 1 {
+                    globals.insert(*slot, instr.clone());
+                }
+            }
+        }
+    }
+    return globals;
+}
+
+/// Whether some END_SCOPE after START might run with only the global scope open, which drops
+/// the globals.
+fn may_leave_global_scope(cfg:&Cfg) -> bool {
+    let instrs = &cfg.decoded.instrs;
+    // The fewest scopes that may be open on entry to each block.
+    let mut depths:Vec<Option<usize>> = vec![None; cfg.blocks.len()];
+    let mut work = vec![];
+    let enter = |depths:&mut Vec<Option<usize>>, work:&mut Vec<usize>, block:usize, depth:usize| {
+        if depths[block].is_none_or(|current| depth < current) {
+            depths[block] = Some(depth);
+            work.push(block);
+        }
+    };
+    if let Some(start) = cfg.start {
+        enter(&mut depths, &mut work, start, 1);
+    }
+    for function in &cfg.decoded.functions {
+        if function.body < instrs.len() {
+            enter(&mut depths, &mut work, cfg.block_of(function.body), 2);
+        }
+    }
+    while let Some(block) = work.pop() {
+        let mut depth = depths[block].unwrap();
+        for instr in &instrs[cfg.blocks[block].instrs.clone()] {
+            match instr {
+                Instr::BeginScope => depth += 1,
+                Instr::EndScope if depth <= 1 => return true,
+                Instr::EndScope => depth -= 1,
+                _ => {},
+            }
+        }
+        for edge in cfg.successors(block).filter(|edge| !matches!(edge.kind, EdgeKind::Call | EdgeKind::Prelude)) {
+            enter(&mut depths, &mut work, edge.to, depth);
+        }
+    }
+    return false;
+}
+
+/// Points JUMPs and COND_JUMPs that land on another JUMP straight at its target, and drops
+/// JUMPs to the instruction after them.
+fn thread_jumps(cfg:&Cfg, instrs:&mut [Option<Instr>]) -> bool {
+    let decoded = &cfg.decoded.instrs;
+    // Where a jump to `target` really ends up, skipping labels and chains of JUMPs.
+    let destination = |target:usize| -> (Option<(u32, usize)>, usize) {
+        let mut seen = HashSet::new();
+        let mut last = None;
+        let mut index = target;
+        loop {
+            while let Some(Instr::Block(_)) = decoded.get(index) {
+                index += 1;
+            }
+            match decoded.get(index) {
+                Some(Instr::Jump(block, Some(next))) if seen.insert(index) => {
+                    last = Some((*block, *next));
+                    index = *next;
+                },
+                _ => return (last, index),
+            }
+        }
+    };
+
+    let mut changed = false;
+    for (index, instr) in decoded.iter().enumerate() {
+        let threaded = match instr {
+            // Falling through ends up in the same place.
+            Instr::Jump(_, Some(target)) if destination(*target).1 == destination(index + 1).1 => None,
+            Instr::Jump(block, Some(target)) => {
+                let (block, next) = destination(*target).0.unwrap_or((*block, *target));
+                Some(Instr::Jump(block, Some(next)))
+            },
+            Instr::CondJump(block, cond, Some(target)) => {
+                let (block, next) = destination(*target).0.unwrap_or((*block, *target));
+                Some(Instr::CondJump(block, *cond, Some(next)))
+            },
+            _ => continue,
+        };
+        if threaded.as_ref() != Some(instr) {
+            instrs[index] = threaded;
+            changed = true;
+        }
+    }
+    return changed;
+}
+
+/// Removes literal assignments and ALLOCAs to variables that are never read afterwards.
+fn remove_dead_stores(cfg:&Cfg, instrs:&mut [Option<Instr>]) -> bool {
+    let decoded = &cfg.decoded.instrs;
+    // Scoping makes a write hide rather than replace the value before it, so with scopes or DEL
+    // around a write only counts as a read-free point, not as the end of the old value's life.
+    let kills = !decoded.iter().any(|instr| matches!(instr, Instr::BeginScope | Instr::EndScope | Instr::Del(_)));
+    // Functions can read the globals whoever calls them.
+    let mut called:HashSet<usize> = HashSet::new();
+    for function in &cfg.decoded.functions {
+        for instr in decoded.iter().take(function.end + 1).skip(function.body) {
+            called.extend(instr.reads());
+        }
+    }
+    let step = |index:usize, prelude:bool, live:&mut HashSet<usize>| {
+        let instr = &decoded[index];
+        // Only literals run before START, so in the prelude nothing else is sure to overwrite.
+        let runs = !prelude || matches!(instr, Instr::Alloca(_) | Instr::Constant(..));
+        if let Some(slot) = instr.writes().filter(|_| kills && runs) {
+            live.remove(&slot);
+        }
+        live.extend(instr.reads());
+        if let Instr::Call(..) = instr {
+            live.extend(called.iter().copied());
+        }
+    };
+
+    // The variables live on entry to each basic block. Calls come back to the same block, so
+    // their edges are left out.
+    let mut ins:Vec<HashSet<usize>> = vec![HashSet::new(); cfg.blocks.len()];
+    let out = |ins:&[HashSet<usize>], block:usize| -> HashSet<usize> {
+        let successors = cfg.successors(block).filter(|edge| edge.kind != EdgeKind::Call);
+        return successors.flat_map(|edge| ins[edge.to].iter().copied()).collect();
+    };
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (block, info) in cfg.blocks.iter().enumerate().rev() {
+            let mut live = out(&ins, block);
+            for index in info.instrs.clone().rev() {
+                step(index, info.prelude, &mut live);
+            }
+            if live != ins[block] {
+                ins[block] = live;
+                changed = true;
+            }
+        }
+    }
+
+    let mut removed = false;
+    for (block, info) in cfg.blocks.iter().enumerate() {
+        let mut live = out(&ins, block);
+        for index in info.instrs.clone().rev() {
+            match &decoded[index] {
+                Instr::Alloca(slot) | Instr::Constant(slot, _) | Instr::Bool(slot, _) if !live.contains(slot) => {
+                    instrs[index] = None;
+                    removed = true;
+                },
+                _ => step(index, info.prelude, &mut live),
+            }
+        }
+    }
+    return removed;
+}
+
+/// Removes basic blocks no path from the start of the program reaches, and functions that are
+/// never called.
+fn remove_unreachable(cfg:&Cfg, instrs:&mut [Option<Instr>]) -> bool {
+    let mut reached = vec![false; cfg.blocks.len()];
+    let mut work:Vec<usize> = cfg.start.into_iter().collect();
+    if !cfg.blocks.is_empty() {
+        work.push(0);
+    }
+    while let Some(block) = work.pop() {
+        if std::mem::replace(&mut reached[block], true) {
+            continue
+        }
+        work.extend(cfg.successors(block).map(|edge| edge.to));
+    }
+
+    let decoded = &cfg.decoded.instrs;
+    let mut changed = false;
+    for (block, info) in cfg.blocks.iter().enumerate() {
+        if reached[block] {
+            continue
+        }
+        for index in info.instrs.clone() {
+            // A called function keeps the instructions that delimit it.
+            let delimits = match decoded[index] {
+                Instr::Func(func) => function_reached(cfg, &reached, func),
+                Instr::End => info.function.is_some_and(|func| function_reached(cfg, &reached, func)),
+                _ => false,
+            };
+            if !delimits {
+                instrs[index] = None;
+                changed = true;
+            }
+        }
+    }
+    for (func, function) in cfg.decoded.functions.iter().enumerate() {
+        if function.body <= function.end && function.end < decoded.len() && !function_reached(cfg, &reached, func) {
+            let removed = instrs[function.body - 1..=function.end].iter().any(Option::is_some);
+            instrs[function.body - 1..=function.end].fill(None);
+            changed |= removed;
+        }
+    }
+    return changed;
+}
+
+fn function_reached(cfg:&Cfg, reached:&[bool], func:usize) -> bool {
+    let body = cfg.decoded.functions[func].body;
+    return body < cfg.decoded.instrs.len() && reached[cfg.block_of(body)];
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::vm::bytecodes::ByteCode;
+    use crate::vm::error::VmErrorKind;
+    use crate::vm::instr::{decode, Instr, Resolver};
+    use crate::vm::io::BufferIo;
+    use crate::vm::opt::{optimize, MAX_LEVEL};
+    use crate::vm::vm::Executor;
+
+    /// Runs a program, giving what it printed and the kind of error it stopped with.
+    fn run(bytecode:ByteCode) -> (String, Option<VmErrorKind>) {
+        let io = BufferIo::new();
+        let result = Executor::new(Box::new(bytecode)).with_io(Box::new(io.clone())).run();
+        return (io.output(), result.err().map(|err| err.kind));
+    }
+
+    fn optimized(src:&str, level:u8) -> ByteCode {
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        return optimize(&program.bytecode, level).unwrap();
+    }
+
+    fn instrs(bytecode:&ByteCode) -> Vec<Instr> {
+        return decode(bytecode, &mut Resolver::new()).unwrap().instrs;
+    }
+
+    const LOOP:&str = "
+        NUM ind 0
+        NUM sum 0
+        NUM inc 1
+        NUM adder -1.289893
+        NUM itterations 1000
+        STR nl \"\\n\"
+
+        BLOCK loop
+            MUL additive ind adder
+            ADD sum sum additive
+
+        ADD ind ind inc
+        LT loopcond ind itterations
+        COND_JUMP loopcond loop
+        JUMP finished
+
+        START
+            LT loopcond ind itterations
+            COND_JUMP loopcond loop
+            BLOCK finished
+            STDOUT sum
+            STDOUT nl
+    ";
+
+    const FOLDING:&str = "
+        NUM two 2
+        INT three 3
+        STR name \"value\"
+        STR nl \"\\n\"
+        START
+            ADD five two three
+            MUL twentyfive five five
+            SUB left twentyfive three
+            LT smaller two three
+            EQ same name name
+            ADD label name five
+            EXP big three two
+            ALLOCA copy
+            STORE copy label
+            NOT unsure smaller
+            AND mixed smaller unsure
+            INT mask 6
+            SHL shifted three mask
+            BXOR flipped shifted mask
+            STDOUT twentyfive
+            STDOUT nl
+            STDOUT left
+            STDOUT smaller
+            STDOUT same
+            STDOUT nl
+            STDOUT copy
+            STDOUT big
+            STDOUT mixed
+            STDOUT flipped
+            COND_JUMP smaller done
+            STDOUT name
+            BLOCK done
+            STDOUT nl
+    ";
+
+    const FUNCTIONS:&str = "
+        INT one 1
+        INT zero 0
+        STR nl \"\\n\"
+        FUNC fact n
+            LTE small n one
+            COND_JUMP small base
+            SUB less n one
+            CALL rest fact less
+            MUL out n rest
+            RET out
+            BLOCK base
+            RET one
+        END
+        FUNC never x
+            STDOUT x
+        END
+        START
+            INT ten 10
+            CALL result fact ten
+            STDOUT result
+            STDOUT nl
+            BEGIN_SCOPE
+                INT one 2
+                ADD shadow one one
+                STDOUT shadow
+            END_SCOPE
+            ADD outer one one
+            STDOUT outer
+            ALLOCA unused
+            NUM dead 1
+            NUM dead 2
+            STDOUT dead
+    ";
+
+    const JUMPS:&str = "
+        STR a \"a\"
+        STR b \"b\"
+        START
+            JUMP first
+            STDOUT a
+            BLOCK first
+            JUMP second
+            BLOCK third
+            STDOUT a
+            JUMP end
+            BLOCK second
+            STDOUT b
+            JUMP third
+            BLOCK end
+            INT zero 0
+            DIV fails b zero
+    ";
+
+    #[test]
+    fn opt_test_stdout_unchanged() {
+        for src in [LOOP, FOLDING, FUNCTIONS, JUMPS] {
+            let expected = run(optimized(src, 0));
+            assert!(!expected.0.is_empty());
+            for level in 1..=MAX_LEVEL + 1 {
+                assert_eq!(run(optimized(src, level)), expected, "level {} changed the output of {}", level, src);
+            }
+        }
+        assert_eq!(run(optimized(LOOP, 0)).0, "-644301.5534999999\n");
+        assert_eq!(run(optimized(FUNCTIONS, 2)).0, "3628800\n422");
+        assert_eq!(run(optimized(JUMPS, 2)), (String::from("ba"), Some(VmErrorKind::DivisionByZero)));
+    }
+
+    #[test]
+    fn opt_test_after_highest_id() {
+        // Once the last id is taken, new constants wrap around to ids the program does not use.
+        let src = format!("INT %{} 5\nINT two 2\nSTART\nADD x two two\nSTDOUT x\nSTDOUT %{}\n", u32::MAX, u32::MAX);
+        let program = Parser::new(src).assemble().unwrap();
+        let loaded = ByteCode::deserialize(&program.bytecode.serialize()).unwrap();
+        for level in 0..=MAX_LEVEL {
+            assert_eq!(run(optimize(&loaded, level).unwrap()), (String::from("45"), None), "level {}", level);
+        }
+    }
+
+    #[test]
+    fn opt_test_folding() {
+        let before = instrs(&optimized(FOLDING, 0));
+        let after = instrs(&optimized(FOLDING, 1));
+        let count = |instrs:&[Instr], pred:fn(&Instr) -> bool| instrs.iter().filter(|instr| pred(instr)).count();
+        assert_eq!(count(&before, |instr| matches!(instr, Instr::Add(..) | Instr::Mul(..) | Instr::Sub(..))), 4);
+        assert_eq!(count(&after, |instr| matches!(instr, Instr::Add(..) | Instr::Mul(..) | Instr::Sub(..))), 0);
+        assert_eq!(count(&after, |instr| matches!(instr, Instr::Lt(..) | Instr::Eq(..) | Instr::Exp(..) | Instr::Store(..))), 0);
+        assert_eq!(count(&after, |instr| matches!(instr, Instr::Not(..) | Instr::And(..) | Instr::Shl(..) | Instr::Bxor(..))), 0);
+        // The branch is known to be taken.
+        assert_eq!(count(&after, |instr| matches!(instr, Instr::CondJump(..))), 0);
+        assert_eq!(count(&after, |instr| matches!(instr, Instr::Jump(..))), 1);
+        assert_eq!(run(optimized(FOLDING, 1)).0, "25\n22truetrue\nvalue59false198\n");
+    }
+
+    #[test]
+    fn opt_test_keeps_errors() {
+        let src = "NUM one 1\nINT zero 0\nINT big 9223372036854775807\nSTART\nDIV a one zero\nADD b big big\n";
+        let after = instrs(&optimized(src, MAX_LEVEL));
+        assert!(after.iter().any(|instr| matches!(instr, Instr::Div(..))));
+        assert!(after.iter().any(|instr| matches!(instr, Instr::Add(..))));
+        // Folding must not hide an error that depends on the scope a value comes from.
+        let scoped = "START\nSTR a \"x\"\nBEGIN_SCOPE\nNUM a 1\nEND_SCOPE\nADD b a a\nSTDOUT b\n";
+        assert_eq!(run(optimized(scoped, MAX_LEVEL)), run(optimized(scoped, 0)));
+    }
+
+    #[test]
+    fn opt_test_dead_code() {
+        let before = instrs(&optimized(FUNCTIONS, 1));
+        let after = instrs(&optimized(FUNCTIONS, 2));
+        // The uncalled function, the ALLOCA and the overwritten NUM are gone, the called one stays.
+        assert_eq!(before.iter().filter(|instr| matches!(instr, Instr::Func(_))).count(), 2);
+        assert_eq!(after.iter().filter(|instr| matches!(instr, Instr::Func(_))).count(), 1);
+        assert!(!after.iter().any(|instr| matches!(instr, Instr::Alloca(_))));
+        assert_eq!(after.iter().filter(|instr| matches!(instr, Instr::Constant(..))).count() + 1,
+            before.iter().filter(|instr| matches!(instr, Instr::Constant(..))).count());
+    }
+
+    #[test]
+    fn opt_test_jump_threading() {
+        let after = instrs(&optimized(JUMPS, 1));
+        // JUMP first lands on JUMP second, so it goes straight there.
+        let jumps:Vec<_> = after.iter().filter_map(|instr| if let Instr::Jump(_, target) = instr {*target} else {None}).collect();
+        assert_eq!(after.iter().filter(|instr| matches!(instr, Instr::Jump(..))).count(), 4);
+        assert!(matches!(after[jumps[0]], Instr::Stdout(_)));
+        let removed = instrs(&optimized(JUMPS, 2));
+        // The STDOUT skipped by the first jump, and the JUMP first..BLOCK first pair, are dropped.
+        assert!(removed.len() < after.len());
+        assert_eq!(removed.iter().filter(|instr| matches!(instr, Instr::Stdout(_))).count(), 2);
+    }
+}
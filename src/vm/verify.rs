@@ -0,0 +1,404 @@
+use std::{collections::HashSet, fmt};
+
+use super::bytecodes::{self as bc, schema, ByteCode, Operand};
+use super::cfg::{Cfg, EdgeKind};
+use super::instr::Instr;
+
+/// Something the verifier found wrong with a program.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Problem {
+    /// Position of the offending instruction's opcode in the bytecode.
+    pub offset:usize,
+    pub opcode:u32,
+    pub message:String
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match bc::mnemonic(self.opcode) {
+            Some(name) => name.to_string(),
+            None => format!("{:#x}", self.opcode),
+        };
+        return write!(f, "verification error in {} at offset {}: {}", name, self.offset, self.message);
+    }
+}
+
+/// Checks a program before it is run, returning every problem found.
+///
+/// Every instruction must be a valid opcode with the right number of operands and a trailing
+/// ENDL, jumps and calls must name defined blocks and functions, START must appear exactly once,
+/// and every id must be defined on every path that reaches a use of it.
+pub fn verify(bytecode:&ByteCode) -> Vec<Problem> {
+    let mut problems = structure(bytecode);
+    // The definition check needs well formed instructions to follow control flow.
+    if problems.is_empty() {
+        match Cfg::new(bytecode) {
+            Ok(cfg) => problems.extend(definitions(&cfg)),
+            Err(err) => problems.push(Problem { offset: err.offset, opcode: err.opcode, message: err.kind.to_string() }),
+        }
+    }
+    return problems;
+}
+
+/// Checks opcodes, operands and the blocks, functions and START they refer to.
+fn structure(bytecode:&ByteCode) -> Vec<Problem> {
+    let mut problems = vec![];
+    let mut problem = |offset:usize, opcode:u32, message:String| problems.push(Problem { offset: offset, opcode: opcode, message: message });
+    let mut starts = vec![];
+    let mut blocks = HashSet::new();
+    let mut functions = HashSet::new();
+    // offset, opcode, id
+    let mut jumps = vec![];
+    let mut calls = vec![];
+    let mut open_function:Option<usize> = None;
+    let next_endl = |from:usize| (from..bytecode.len()).find(|word| bytecode.at(*word) == bc::ENDL);
+    let mut pos = 0;
+    while pos < bytecode.len() {
+        let opcode = bytecode.at(pos);
+        let Some(schema) = schema(opcode) else {
+            if !(bc::ENDL..bc::__MAX_INSTR_INT__).contains(&opcode) {
+                problem(pos, opcode, format!("{:#x} is not an opcode", opcode));
+            }
+            else {
+                problem(pos, opcode, String::from("not the start of an instruction"));
+            }
+            // Carry on from the next instruction boundary we can find.
+            pos = next_endl(pos).map_or(bytecode.len(), |endl| endl + 1);
+            continue
+        };
+        let name = bc::mnemonic(opcode).unwrap_or("instruction");
+        let fixed = schema.operands.len();
+        let (count, next) = if schema.endl {
+            let Some(endl) = next_endl(pos + 1) else {
+                problem(pos, opcode, format!("{} is missing its ENDL", name));
+                break
+            };
+            (endl - pos - 1, endl + 1)
+        }
+        else {
+            (fixed, pos + 1 + fixed)
+        };
+        if next > bytecode.len() {
+            problem(pos, opcode, format!("{} is cut short", name));
+            break
+        }
+        let (min, max) = match opcode {
+            bc::INDEX | bc::STORE_INDEX => (fixed + 1, None),
+            bc::RET => (0, Some(1)),
+            _ if schema.variadic => (fixed, None),
+            _ => (fixed, Some(fixed)),
+        };
+        if count < min || max.is_some_and(|max| count > max) {
+            let expected = match max {
+                Some(max) if max == min => format!("{}", min),
+                Some(max) if count > max => format!("at most {}", max),
+                _ => format!("at least {}", min),
+            };
+            problem(pos, opcode, format!("{} expects {} operands, found {}", name, expected, count));
+            pos = next;
+            continue
+        }
+        for (index, operand) in schema.operands.iter().enumerate() {
+            let word = bytecode.at(pos + 1 + index);
+            match operand {
+                Operand::Const if bytecode.constant(word).is_none() => {
+                    problem(pos, opcode, format!("constant {} is not in the constant pool", word));
+                },
+                Operand::Flag if word > 1 => problem(pos, opcode, format!("expected a flag of 0 or 1, found {}", word)),
+                _ => {}
+            }
+        }
+        match opcode {
+            bc::START => starts.push(pos),
+            bc::BLOCK => {blocks.insert(bytecode.at(pos + 1));},
+            bc::JUMP | bc::COND_JUMP => jumps.push((pos, opcode, bytecode.at(pos + 1))),
+            bc::CALL => calls.push((pos, bytecode.at(pos + 2))),
+            bc::FUNC => {
+                functions.insert(bytecode.at(pos + 1));
+                if open_function.is_some() {
+                    problem(pos, opcode, String::from("FUNC cannot be nested"));
+                }
+                else {
+                    open_function = Some(pos);
+                }
+            },
+            bc::END if open_function.take().is_none() => problem(pos, opcode, String::from("END without a matching FUNC")),
+            bc::RET if open_function.is_none() => problem(pos, opcode, String::from("RET outside of a function")),
+            _ => {}
+        }
+        pos = next;
+    }
+    if let Some(offset) = open_function {
+        problem(offset, bc::FUNC, String::from("FUNC has no matching END"));
+    }
+    for (offset, opcode, block) in jumps {
+        if !blocks.contains(&block) {
+            problem(offset, opcode, format!("jump to undefined block {}", block));
+        }
+    }
+    for (offset, func) in calls {
+        if !functions.contains(&func) {
+            problem(offset, bc::CALL, format!("call to undefined function {}", func));
+        }
+    }
+    match starts.as_slice() {
+        [] => problem(bytecode.len(), bc::START, String::from("the program has no START")),
+        [first, rest @ ..] => for offset in rest {
+            problem(*offset, bc::START, format!("START already appeared at offset {}", first));
+        },
+    }
+    return problems;
+}
+
+/// The variables defined at a point in the program, innermost scope last.
+type Scopes = Vec<HashSet<usize>>;
+
+/// Combines the variables defined along two paths into the ones defined along both.
+fn meet(current:&Scopes, other:&Scopes) -> Scopes {
+    // Scopes left open along only one of the paths fold into the innermost shared one.
+    let depth = current.len().min(other.len());
+    let fold = |scopes:&Scopes| {
+        let mut folded = scopes[..depth].to_vec();
+        for extra in &scopes[depth..] {
+            folded[depth - 1].extend(extra);
+        }
+        folded
+    };
+    return fold(current).iter().zip(fold(other)).map(|(lhs, rhs)| lhs.intersection(&rhs).copied().collect()).collect();
+}
+
+fn defined(scopes:&Scopes, slot:usize) -> bool {
+    return scopes.iter().any(|scope| scope.contains(&slot));
+}
+
+/// Applies the definitions an instruction makes.
+fn transfer(instr:&Instr, scopes:&mut Scopes) {
+    match instr {
+        Instr::BeginScope => scopes.push(HashSet::new()),
+        Instr::EndScope => {
+            scopes.pop();
+            if scopes.is_empty() {
+                scopes.push(HashSet::new());
+            }
+        },
+        // Deleting uncovers whatever the innermost binding hid.
+        Instr::Del(slot) => if let Some(scope) = scopes.iter_mut().rev().find(|scope| scope.contains(slot)) {
+            scope.remove(slot);
+        },
+        _ => if let Some(slot) = instr.writes() {
+            scopes.last_mut().unwrap().insert(slot);
+        },
+    }
+}
+
+/// Checks that every variable read is defined on every path reaching the read.
+fn definitions(cfg:&Cfg) -> Vec<Problem> {
+    let instrs = &cfg.decoded.instrs;
+    let Some(start) = cfg.start else {
+        return vec![];
+    };
+    // Before START only ALLOCA and literals run.
+    let mut globals = HashSet::new();
+    for block in cfg.blocks.iter().filter(|block| block.prelude) {
+        for instr in &instrs[block.instrs.clone()] {
+            if let Instr::Alloca(slot) | Instr::Constant(slot, _) = instr {
+                globals.insert(*slot);
+            }
+        }
+    }
+
+    // The variables defined on entry to each basic block.
+    let mut states:Vec<Option<Scopes>> = vec![None; cfg.blocks.len()];
+    let mut work = vec![];
+    let enter = |states:&mut Vec<Option<Scopes>>, work:&mut Vec<usize>, block:usize, scopes:Scopes| {
+        let merged = match &states[block] {
+            Some(current) => meet(current, &scopes),
+            None => scopes,
+        };
+        if states[block].as_ref() != Some(&merged) {
+            states[block] = Some(merged);
+            work.push(block);
+        }
+    };
+    enter(&mut states, &mut work, start, vec![globals.clone()]);
+    // A function sees the globals and its own parameters, whoever calls it, as a CALL's frame does.
+    for function in &cfg.decoded.functions {
+        if function.body < instrs.len() {
+            let params = function.params.iter().copied().collect();
+            enter(&mut states, &mut work, cfg.block_of(function.body), vec![globals.clone(), params]);
+        }
+    }
+    while let Some(block) = work.pop() {
+        let mut scopes = states[block].clone().unwrap();
+        for instr in &instrs[cfg.blocks[block].instrs.clone()] {
+            transfer(instr, &mut scopes);
+        }
+        // Calls come back to the same block, and the prelude has already been accounted for.
+        for edge in cfg.successors(block).filter(|edge| !matches!(edge.kind, EdgeKind::Call | EdgeKind::Prelude)) {
+            enter(&mut states, &mut work, edge.to, scopes.clone());
+        }
+    }
+
+    let mut problems = vec![];
+    for (block, state) in states.into_iter().enumerate() {
+        let Some(mut scopes) = state else {
+            continue
+        };
+        for index in cfg.blocks[block].instrs.clone() {
+            let mut reported = HashSet::new();
+            for slot in instrs[index].reads() {
+                if !defined(&scopes, slot) && reported.insert(slot) {
+                    let (offset, opcode) = cfg.decoded.origins[index];
+                    let message = format!("id {} is not defined on every path that reaches it", cfg.resolver.id(slot));
+                    problems.push(Problem { offset: offset, opcode: opcode, message: message });
+                }
+            }
+            transfer(&instrs[index], &mut scopes);
+        }
+    }
+    return problems;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::vm::bytecodes::{self as bc, BytecodeBuilder};
+    use crate::vm::error::VmErrorKind;
+    use crate::vm::io::BufferIo;
+    use crate::vm::verify::verify;
+    use crate::vm::vm::Executor;
+
+    fn messages(src:&str) -> Vec<String> {
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        return verify(&program.bytecode).into_iter().map(|problem| problem.message).collect();
+    }
+
+    #[test]
+    fn verify_test_accepts_valid_programs() {
+        let src = "
+            NUM ind 0
+            NUM inc 1
+            NUM limit 10
+            BLOCK loop
+                ADD ind ind inc
+            LT again ind limit
+            COND_JUMP again loop
+            JUMP finished
+
+            FUNC twice value
+                ADD doubled value value
+                RET doubled
+            END
+
+            START
+                LT again ind limit
+                COND_JUMP again loop
+                BLOCK finished
+                CALL four twice inc
+                BEGIN_SCOPE
+                    STR text \"inside\"
+                    STDOUT text
+                END_SCOPE
+                STDOUT four
+        ";
+        assert_eq!(messages(src), Vec::<String>::new());
+    }
+
+    #[test]
+    fn verify_test_definitions() {
+        let src = "
+            START
+                NUM one 1
+                BEGIN_SCOPE
+                    NUM scoped 2
+                END_SCOPE
+                STDOUT scoped
+                BOOL flag true
+                COND_JUMP flag skip
+                NUM maybe 3
+                BLOCK skip
+                STDOUT maybe
+                STDOUT one
+                DEL one
+                STDOUT one
+        ";
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let problems = verify(&program.bytecode);
+        let ids:Vec<u32> = problems.iter()
+            .map(|problem| problem.message.split(' ').nth(1).unwrap().parse().unwrap())
+            .collect();
+        assert_eq!(ids, vec![program.symbols["scoped"], program.symbols["maybe"], program.symbols["one"]]);
+        assert!(problems.iter().all(|problem| problem.opcode == bc::STDOUT));
+    }
+
+    #[test]
+    fn verify_test_function_scope() {
+        // A function sees the globals and its parameters but not its caller's variables, as at runtime.
+        let src = "
+            NUM g 2
+            FUNC twice n
+                ADD out g n
+                RET out
+            END
+            START
+                STR x \"hi\"
+                CALL four twice g
+                FUNC show
+                    STDOUT x
+                END
+                CALL nothing show
+        ";
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let problems = verify(&program.bytecode);
+        assert_eq!(problems.len(), 1);
+        assert_eq!(problems[0].opcode, bc::STDOUT);
+        assert_eq!(problems[0].message, format!("id {} is not defined on every path that reaches it", program.symbols["x"]));
+        let err = Executor::new(program.bytecode).with_io(Box::new(BufferIo::new())).run().unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::UnknownMemory(program.symbols["x"]));
+        assert_eq!(err.offset, problems[0].offset);
+    }
+
+    #[test]
+    fn verify_test_structure() {
+        let mut bb = BytecodeBuilder::new();
+        let one = bb.write_num(1.0, None);
+        bb.write_jump(0x999);
+        bb.write_start();
+        let add = bb.src.len();
+        bb.write_add(one, one, None);
+        bb.write_stdout(one);
+        bb.write_call(0x998, vec![], None);
+        bb.write_ret(None);
+        // Drop an operand from the ADD, leaving a bogus opcode where its ENDL was.
+        bb.src.set(add + 3, bc::ENDL);
+        bb.src.set(add + 4, 0x3);
+        let problems = verify(&bb.src);
+        let messages:Vec<&str> = problems.iter().map(|problem| problem.message.as_str()).collect();
+        assert_eq!(messages, vec![
+            "ADD expects 3 operands, found 2",
+            "0x3 is not an opcode",
+            "RET outside of a function",
+            "jump to undefined block 2457",
+            "call to undefined function 2456",
+        ]);
+        assert_eq!(problems[0].offset, add);
+        assert_eq!(problems[0].to_string(), format!("verification error in ADD at offset {}: ADD expects 3 operands, found 2", add));
+
+        let mut bb = BytecodeBuilder::new();
+        bb.write_num(1.0, None);
+        let len = bb.src.len();
+        bb.src.set(len - 1, bc::START);
+        assert_eq!(verify(&bb.src)[0].message, "NUM is missing its ENDL");
+
+        let mut bb = BytecodeBuilder::new();
+        bb.write_bool(true, None);
+        bb.src.set(2, 7);
+        let messages:Vec<String> = verify(&bb.src).into_iter().map(|problem| problem.message).collect();
+        assert_eq!(messages, vec!["expected a flag of 0 or 1, found 7", "the program has no START"]);
+
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        bb.write_start();
+        assert_eq!(verify(&bb.src)[0].message, "START already appeared at offset 0");
+    }
+}
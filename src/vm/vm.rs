@@ -1,51 +1,226 @@
-use std::{collections::HashMap, io::stdin, ops::{Add, Div, Index, IndexMut, Mul, Rem, Sub}};
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc, fs::{File, OpenOptions}, io::{Read, Write}, ops::{Add, Div, Mul, Rem, Sub}};
 
-use super::bytecodes::{ByteCode, ByteType};
+use super::bytecodes::{self as bc, ByteCode, Constant};
+use super::instr::{decode, Decoded, Function, Instr, MathOp, Resolver};
+use super::error::{VmError, VmErrorKind};
+use super::io::{Io, StdIo};
+use super::debug::{Breakpoint, DebugInfo, Frame, Pause};
 
-use super::bytecodes as bc;
-
-#[derive(Clone)]
+#[derive(Clone, Debug)]
 enum ScalarType {
-    Int(i32),
-    Float(f32),
+    Int(i64),
+    Float(f64),
     Str(String),
     Bool(bool),
+    /// An index into the executor's table of open files.
+    File(usize),
+    /// Lists are shared by reference, so copies see each other's changes.
+    List(Rc<RefCell<Vec<ScalarType>>>),
     None
 }
 
 impl ScalarType {
-    fn pow(&mut self, other:Self) -> f32 {
-        return match (self, other) {
-            (Self::Int(l0), Self::Int(r0)) => l0.pow(r0 as u32) as f32,
-            (Self::Float(l0), Self::Float(r0)) => l0.powf(r0),
-            (Self::Int(l0), Self::Float(r0)) => (*l0 as f32).powf(r0),
-            (Self::Float(l0), Self::Int(r0)) => l0.powf(r0 as f32),
-            _ => 0.0,
+    fn pow(self, other:Self) -> Result<f64, VmErrorKind> {
+        return match (self.as_float(), other.as_float()) {
+            (Some(l0), Some(r0)) => Ok(l0.powf(r0)),
+            _ => Err(illegal("EXP", &self, &other)),
+        };
+    }
+
+    /// Ints and bools as an int, the way the arithmetic operators treat bools.
+    fn as_int(&self) -> Option<i64> {
+        return match self {
+            Self::Int(val) => Some(*val),
+            Self::Bool(val) => Some(*val as i64),
+            _ => None,
+        };
+    }
+
+    fn as_float(&self) -> Option<f64> {
+        return match self {
+            Self::Float(val) => Some(*val),
+            other => other.as_int().map(|val| val as f64),
+        };
+    }
+
+    /// The single operand math intrinsics.
+    fn math(self, op:MathOp) -> Result<Self, VmErrorKind> {
+        let name = bc::mnemonic(op.opcode()).unwrap();
+        let mismatch = || VmErrorKind::TypeMismatch(format!("cannot {} {}", name, self.type_name()));
+        if let (Some(val), Self::Int(_) | Self::Bool(_)) = (self.as_int(), &self) {
+            let int = match op {
+                MathOp::Neg => Some(val.checked_neg().ok_or_else(|| overflow(name))?),
+                MathOp::Abs => Some(val.checked_abs().ok_or_else(|| overflow(name))?),
+                MathOp::Floor | MathOp::Ceil | MathOp::Round => Some(val),
+                _ => None,
+            };
+            if let Some(int) = int {
+                return Ok(Self::Int(int));
+            }
         }
+        let val = self.as_float().ok_or_else(mismatch)?;
+        let domain = |ok:bool| if ok {Ok(())} else {Err(VmErrorKind::Domain(format!("{} of {}", name, val)))};
+        return Ok(Self::Float(match op {
+            MathOp::Neg => -val,
+            MathOp::Abs => val.abs(),
+            MathOp::Floor => val.floor(),
+            MathOp::Ceil => val.ceil(),
+            MathOp::Round => val.round(),
+            MathOp::Sqrt => {domain(val.is_nan() || val >= 0.0)?; val.sqrt()},
+            MathOp::Sin => val.sin(),
+            MathOp::Cos => val.cos(),
+            MathOp::Log => {domain(val.is_nan() || val > 0.0)?; val.ln()},
+        }));
+    }
+
+    /// MIN and MAX, promoting like ADD: ints and bools mixed stay ints, two bools or any float give floats.
+    fn pick(self, op:&str, other:Self, smaller:bool) -> Result<Self, VmErrorKind> {
+        return match (&self, &other) {
+            (Self::Int(_), Self::Int(_) | Self::Bool(_)) | (Self::Bool(_), Self::Int(_)) => {
+                let (l0, r0) = (self.as_int().unwrap(), other.as_int().unwrap());
+                Ok(Self::Int(if smaller {l0.min(r0)} else {l0.max(r0)}))
+            },
+            _ => match (self.as_float(), other.as_float()) {
+                (Some(l0), Some(r0)) => Ok(Self::Float(if smaller {l0.min(r0)} else {l0.max(r0)})),
+                _ => Err(illegal(op, &self, &other)),
+            },
+        };
+    }
+
+    /// AND and OR, which only combine bools.
+    fn logical(self, op:&str, other:Self, f:fn(bool, bool) -> bool) -> Result<Self, VmErrorKind> {
+        return match (self, other) {
+            (Self::Bool(l0), Self::Bool(r0)) => Ok(Self::Bool(f(l0, r0))),
+            (lhs, rhs) => Err(illegal(op, &lhs, &rhs)),
+        };
+    }
+
+    fn not(self) -> Result<Self, VmErrorKind> {
+        return match self {
+            Self::Bool(val) => Ok(Self::Bool(!val)),
+            val => Err(VmErrorKind::TypeMismatch(format!("cannot NOT {}", val.type_name()))),
+        };
+    }
+
+    /// BAND, BOR and BXOR, which only combine ints.
+    fn bitwise(self, op:&str, other:Self, f:fn(i64, i64) -> i64) -> Result<Self, VmErrorKind> {
+        return match (self, other) {
+            (Self::Int(l0), Self::Int(r0)) => Ok(Self::Int(f(l0, r0))),
+            (lhs, rhs) => Err(illegal(op, &lhs, &rhs)),
+        };
+    }
+
+    /// SHL and SHR, where shifting by a negative amount or by 64 or more overflows.
+    fn shift(self, op:&str, other:Self, f:fn(i64, u32) -> Option<i64>) -> Result<Self, VmErrorKind> {
+        return match (self, other) {
+            (Self::Int(l0), Self::Int(r0)) => {
+                u32::try_from(r0).ok().and_then(|r0| f(l0, r0)).map(Self::Int).ok_or_else(|| overflow(op))
+            },
+            (lhs, rhs) => Err(illegal(op, &lhs, &rhs)),
+        };
+    }
+
+    fn type_name(&self) -> &'static str {
+        return match self {
+            Self::Int(_) => "int",
+            Self::Float(_) => "float",
+            Self::Str(_) => "str",
+            Self::Bool(_) => "bool",
+            Self::File(_) => "file",
+            Self::List(_) => "list",
+            Self::None => "null",
+        };
+    }
+
+    fn is_zero(&self) -> bool {
+        return match self {
+            Self::Int(val) => *val == 0,
+            Self::Float(val) => *val == 0.0,
+            Self::Bool(val) => !val,
+            _ => false,
+        };
+    }
+}
+
+impl ScalarType {
+    /// How the debugger and REPL show a value: as STDOUT prints it, but with strings quoted.
+    fn render(&self) -> String {
+        return match self {
+            ScalarType::Str(val) => format!("{:?}", val),
+            val => val.to_string(),
+        };
     }
 }
 
+impl fmt::Display for ScalarType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        return match self {
+            Self::Int(val) => write!(f, "{}", val),
+            Self::Float(val) => write!(f, "{}", val),
+            Self::Str(val) => write!(f, "{}", val),
+            Self::Bool(val) => write!(f, "{}", if *val {"true"} else {"false"}),
+            Self::File(handle) => write!(f, "<file {}>", handle),
+            Self::List(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.borrow().iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    match item {
+                        Self::Str(val) => write!(f, "{:?}", val)?,
+                        _ => write!(f, "{}", item)?,
+                    }
+                }
+                write!(f, "]")
+            },
+            Self::None => write!(f, "Null"),
+        };
+    }
+}
+
+/// Resolves a list index, counting negative indices from the end.
+fn list_index(index:&ScalarType, len:usize) -> Result<usize, VmErrorKind> {
+    let index = match index {
+        ScalarType::Int(val) => *val,
+        ScalarType::Float(val) if val.fract() == 0.0 => *val as i64,
+        other => return Err(VmErrorKind::TypeMismatch(format!("list index must be a whole number, found {}", other))),
+    };
+    let resolved = if index < 0 {index + len as i64} else {index};
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(VmErrorKind::IndexOutOfBounds(index, len));
+    }
+    return Ok(resolved as usize);
+}
+
+fn overflow(op:&str) -> VmErrorKind {
+    return VmErrorKind::Overflow(String::from(op));
+}
+
+fn illegal(op:&str, lhs:&ScalarType, rhs:&ScalarType) -> VmErrorKind {
+    return VmErrorKind::TypeMismatch(format!("cannot {} {} and {}", op, lhs.type_name(), rhs.type_name()));
+}
+
 impl Add for ScalarType {
-    type Output = Self;
+    type Output = Result<Self, VmErrorKind>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        return match (self, rhs) {
-            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0 + r0),
+        return Ok(match (self, rhs) {
+            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0.checked_add(r0).ok_or_else(|| overflow("ADD"))?),
             
             (Self::Float(l0), Self::Float(r0)) => Self::Float(l0 + r0),
             
-            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f32 + r0),
-            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 + r0 as f32),
+            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f64 + r0),
+            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 + r0 as f64),
             
             (Self::Str(l0), Self::Str(r0)) => Self::Str(l0 + &r0),
             
-            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i32) as f32 + (r0 as i32) as f32),
+            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i64) as f64 + (r0 as i64) as f64),
             
-            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0 + r0 as i32),
-            (Self::Bool(l0), Self::Int(r0)) => Self::Int(l0 as i32 + r0),
+            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0.checked_add(r0 as i64).ok_or_else(|| overflow("ADD"))?),
+            (Self::Bool(l0), Self::Int(r0)) => Self::Int((l0 as i64).checked_add(r0).ok_or_else(|| overflow("ADD"))?),
 
-            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 + (r0 as i32) as f32),
-            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i32) as f32 + r0),
+            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 + (r0 as i64) as f64),
+            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i64) as f64 + r0),
             
             (Self::Str(l0), Self::Bool(r0)) => Self::Str(l0 + if r0 {"true"} else {"false"}),
             (Self::Bool(l0), Self::Str(r0)) => Self::Str(if l0 {"true"} else {"false"}.to_string() + &r0),
@@ -58,32 +233,32 @@ impl Add for ScalarType {
 
             (Self::Str(l0), Self::None) => Self::Str(format!("{}Null", l0)),
             (Self::None, Self::Str(r0)) => Self::Str(format!("Null{}", r0)),
-            _ => panic!("Illegal ADD operation!"),
-        }
+            (lhs, rhs) => return Err(illegal("ADD", &lhs, &rhs)),
+        });
     }
 }
 
 impl Sub for ScalarType {
-    type Output = Self;
+    type Output = Result<Self, VmErrorKind>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        return match (self, rhs) {
-            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0 - r0),
+        return Ok(match (self, rhs) {
+            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0.checked_sub(r0).ok_or_else(|| overflow("SUB"))?),
             
             (Self::Float(l0), Self::Float(r0)) => Self::Float(l0 - r0),
             
-            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f32 - r0),
-            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 - r0 as f32),
+            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f64 - r0),
+            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 - r0 as f64),
             
             (Self::Str(l0), Self::Str(r0)) => Self::Str(l0.replace(&r0, "")),
             
-            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i32) as f32 - (r0 as i32) as f32),
+            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i64) as f64 - (r0 as i64) as f64),
             
-            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0 - r0 as i32),
-            (Self::Bool(l0), Self::Int(r0)) => Self::Int(l0 as i32 - r0),
+            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0.checked_sub(r0 as i64).ok_or_else(|| overflow("SUB"))?),
+            (Self::Bool(l0), Self::Int(r0)) => Self::Int((l0 as i64).checked_sub(r0).ok_or_else(|| overflow("SUB"))?),
 
-            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 - (r0 as i32) as f32),
-            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i32) as f32 - r0),
+            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 - (r0 as i64) as f64),
+            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i64) as f64 - r0),
             
             (Self::Str(l0), Self::Bool(r0)) => Self::Str(l0.replace(if r0 {"true"} else {"false"}, "")),
             
@@ -93,89 +268,89 @@ impl Sub for ScalarType {
 
             (Self::Str(l0), Self::None) => Self::Str(l0.replace("Null", "")),
             
-            _ => panic!("Illegal SUB operation!"),
-        }
+            (lhs, rhs) => return Err(illegal("SUB", &lhs, &rhs)),
+        });
     }
 }
 
 impl Mul for ScalarType {
-    type Output = Self;
+    type Output = Result<Self, VmErrorKind>;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        return match (self, rhs) {
-            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0 * r0),
+        return Ok(match (self, rhs) {
+            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0.checked_mul(r0).ok_or_else(|| overflow("MUL"))?),
             
             (Self::Float(l0), Self::Float(r0)) => Self::Float(l0 * r0),
             
-            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f32 * r0),
-            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 * r0 as f32),
+            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f64 * r0),
+            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 * r0 as f64),
                         
-            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i32) as f32 * (r0 as i32) as f32),
+            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i64) as f64 * (r0 as i64) as f64),
             
-            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0 * r0 as i32),
-            (Self::Bool(l0), Self::Int(r0)) => Self::Int(l0 as i32 * r0),
+            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0.checked_mul(r0 as i64).ok_or_else(|| overflow("MUL"))?),
+            (Self::Bool(l0), Self::Int(r0)) => Self::Int((l0 as i64).checked_mul(r0).ok_or_else(|| overflow("MUL"))?),
 
-            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 * (r0 as i32) as f32),
-            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i32) as f32 * r0),
-                        
-            (Self::Str(l0), Self::Int(r0)) => todo!(),
-            (Self::Int(l0), Self::Str(r0)) => todo!(),
-            
-            (Self::Str(l0), Self::Float(r0)) => todo!(),
-            (Self::Float(l0), Self::Str(r0)) => todo!(),
+            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 * (r0 as i64) as f64),
+            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i64) as f64 * r0),
 
-            _ => panic!("Illegal MUL operation!"),
-        }
+            (lhs, rhs) => return Err(illegal("MUL", &lhs, &rhs)),
+        });
     }
 }
 
 impl Div for ScalarType {
-    type Output = Self;
+    type Output = Result<Self, VmErrorKind>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        return match (self, rhs) {
-            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0 / r0),
+        if rhs.is_zero() {
+            return Err(VmErrorKind::DivisionByZero);
+        }
+        return Ok(match (self, rhs) {
+            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0.checked_div(r0).ok_or_else(|| overflow("DIV"))?),
             
             (Self::Float(l0), Self::Float(r0)) => Self::Float(l0 / r0),
             
-            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f32 / r0),
-            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 / r0 as f32),
+            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f64 / r0),
+            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 / r0 as f64),
                         
-            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i32) as f32 / (r0 as i32) as f32),
+            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i64) as f64 / (r0 as i64) as f64),
             
-            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0 / r0 as i32),
-            (Self::Bool(l0), Self::Int(r0)) => Self::Int(l0 as i32 / r0),
+            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0.checked_div(r0 as i64).ok_or_else(|| overflow("DIV"))?),
+            (Self::Bool(l0), Self::Int(r0)) => Self::Int((l0 as i64).checked_div(r0).ok_or_else(|| overflow("DIV"))?),
 
-            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 / (r0 as i32) as f32),
-            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i32) as f32 / r0),
+            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 / (r0 as i64) as f64),
+            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i64) as f64 / r0),
 
-            _ => panic!("Illegal DIV operation!"),
-        }
+            (lhs, rhs) => return Err(illegal("DIV", &lhs, &rhs)),
+        });
     }
 }
 
 impl Rem for ScalarType {
-    type Output = Self;
+    type Output = Result<Self, VmErrorKind>;
 
     fn rem(self, rhs: Self) -> Self::Output {
-        return match (self, rhs) {
-            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0 % r0),
+        if rhs.is_zero() {
+            return Err(VmErrorKind::DivisionByZero);
+        }
+        return Ok(match (self, rhs) {
+            (Self::Int(l0), Self::Int(r0)) => Self::Int(l0.checked_rem(r0).ok_or_else(|| overflow("MOD"))?),
             
             (Self::Float(l0), Self::Float(r0)) => Self::Float(l0 % r0),
             
-            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f32 % r0),
-            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 % r0 as f32),
+            (Self::Int(l0), Self::Float(r0)) => Self::Float(l0 as f64 % r0),
+            (Self::Float(l0), Self::Int(r0)) => Self::Float(l0 % r0 as f64),
                         
-            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i32) as f32 % (r0 as i32) as f32),
+            (Self::Bool(l0), Self::Bool(r0)) => Self::Float((l0 as i64) as f64 % (r0 as i64) as f64),
             
-            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0 % r0 as i32),
-            (Self::Bool(l0), Self::Int(r0)) => Self::Int(l0 as i32 % r0),
+            (Self::Int(l0), Self::Bool(r0)) => Self::Int(l0.checked_rem(r0 as i64).ok_or_else(|| overflow("MOD"))?),
+            (Self::Bool(l0), Self::Int(r0)) => Self::Int((l0 as i64).checked_rem(r0).ok_or_else(|| overflow("MOD"))?),
 
-            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 % (r0 as i32) as f32),
-            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i32) as f32 % r0),
+            (Self::Float(l0), Self::Bool(r0)) => Self::Float(l0 % (r0 as i64) as f64),
+            (Self::Bool(l0), Self::Float(r0)) => Self::Float((l0 as i64) as f64 % r0),
 
-            _ => panic!("Illegal MUL operation!"),
-        }
+            (lhs, rhs) => return Err(illegal("MOD", &lhs, &rhs)),
+        });
     }
 }
 
@@ -184,8 +359,12 @@ impl PartialEq for ScalarType {
         match (self, other) {
             (Self::Int(l0), Self::Int(r0)) => l0 == r0,
             (Self::Float(l0), Self::Float(r0)) => l0 == r0,
+            (Self::Int(l0), Self::Float(r0)) => *l0 as f64 == *r0,
+            (Self::Float(l0), Self::Int(r0)) => *l0 == *r0 as f64,
             (Self::Str(l0), Self::Str(r0)) => l0 == r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 == r0,
+            (Self::File(l0), Self::File(r0)) => l0 == r0,
+            (Self::List(l0), Self::List(r0)) => *l0.borrow() == *r0.borrow(),
             _ => false,
         }
     }
@@ -196,6 +375,8 @@ impl PartialOrd for ScalarType {
         match (self, other) {
             (Self::Int(l0), Self::Int(r0)) => l0 < r0,
             (Self::Float(l0), Self::Float(r0)) => l0 < r0,
+            (Self::Int(l0), Self::Float(r0)) => (*l0 as f64) < *r0,
+            (Self::Float(l0), Self::Int(r0)) => *l0 < *r0 as f64,
             (Self::Str(l0), Self::Str(r0)) => l0 < r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 < r0,
             _ => false,
@@ -206,6 +387,8 @@ impl PartialOrd for ScalarType {
         match (self, other) {
             (Self::Int(l0), Self::Int(r0)) => l0 <= r0,
             (Self::Float(l0), Self::Float(r0)) => l0 <= r0,
+            (Self::Int(l0), Self::Float(r0)) => (*l0 as f64) <= *r0,
+            (Self::Float(l0), Self::Int(r0)) => *l0 <= *r0 as f64,
             (Self::Str(l0), Self::Str(r0)) => l0 <= r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 <= r0,
             _ => false,
@@ -216,6 +399,8 @@ impl PartialOrd for ScalarType {
         match (self, other) {
             (Self::Int(l0), Self::Int(r0)) => l0 > r0,
             (Self::Float(l0), Self::Float(r0)) => l0 > r0,
+            (Self::Int(l0), Self::Float(r0)) => (*l0 as f64) > *r0,
+            (Self::Float(l0), Self::Int(r0)) => *l0 > *r0 as f64,
             (Self::Str(l0), Self::Str(r0)) => l0 > r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 > r0,
             _ => false,
@@ -226,6 +411,8 @@ impl PartialOrd for ScalarType {
         match (self, other) {
             (Self::Int(l0), Self::Int(r0)) => l0 >= r0,
             (Self::Float(l0), Self::Float(r0)) => l0 >= r0,
+            (Self::Int(l0), Self::Float(r0)) => (*l0 as f64) >= *r0,
+            (Self::Float(l0), Self::Int(r0)) => *l0 >= *r0 as f64,
             (Self::Str(l0), Self::Str(r0)) => l0 >= r0,
             (Self::Bool(l0), Self::Bool(r0)) => l0 >= r0,
             _ => false,
@@ -248,385 +435,657 @@ impl PartialOrd for ScalarType {
     }
 }
 
-type StackFrame = HashMap<u32, ScalarType>;
+/// A binding that a scope hid by setting the same slot, put back when the scope ends.
+struct Saved {
+    slot:usize,
+    value:ScalarType,
+    depth:usize,
+    /// Cleared once DEL has already put the binding back.
+    live:bool
+}
 
+/// Variable storage indexed by the slots `Resolver` hands out.
+///
+/// Each slot holds its innermost binding. Setting a slot that was bound in an outer scope
+/// (or not at all) saves the old binding in the current scope, and ending the scope restores it,
+/// so a scope is the range of `saved` that it pushed.
+///
+/// A CALL opens a frame, a scope that sees the globals but none of the caller's bindings.
 struct ScopeStack {
-    stack:Vec<StackFrame>
+    resolver:Resolver,
+    values:Vec<ScalarType>,
+    /// The scope depth each slot was bound at, 0 while unbound.
+    depths:Vec<usize>,
+    saved:Vec<Saved>,
+    /// Where each scope's range of `saved` starts.
+    scopes:Vec<usize>,
+    /// Slots bound before START, which stay visible inside every frame.
+    globals:Vec<bool>,
+    /// Whether bindings in the outermost scope become globals too, as they do in pieces run with
+    /// `Executor::run_appended`.
+    outermost_globals:bool,
+    /// The depth of the innermost frame's first scope, bindings below it are hidden.
+    floor:usize,
+    /// The floors of the frames below the innermost one.
+    floors:Vec<usize>
 }
 
 impl ScopeStack {
     fn new() -> ScopeStack {
         ScopeStack {
-            stack: vec![HashMap::new()]
+            resolver: Resolver::new(),
+            values: vec![],
+            depths: vec![],
+            saved: vec![],
+            scopes: vec![0],
+            globals: vec![],
+            outermost_globals: false,
+            floor: 1,
+            floors: vec![]
         }
     }
 
-    fn top(&mut self) -> &mut StackFrame {
-        return self.stack.last_mut().unwrap();
+    /// Makes room for every slot the resolver has handed out.
+    fn grow(&mut self) -> () {
+        self.values.resize(self.resolver.len(), ScalarType::None);
+        self.depths.resize(self.resolver.len(), 0);
+        self.globals.resize(self.resolver.len(), false);
     }
 
     fn new_scope(&mut self) -> () {
-        self.stack.push(HashMap::new())
+        self.scopes.push(self.saved.len())
     }
 
-    fn alloca(&mut self, key:u32) -> () {
-        self.top().insert(key, ScalarType::None);
+    /// Opens a scope for a function call, hiding everything but the globals.
+    fn new_frame(&mut self) -> () {
+        self.new_scope();
+        self.floors.push(self.floor);
+        self.floor = self.scopes.len();
     }
 
-    fn set(&mut self, key:u32, val:ScalarType) {
-        self.top().insert(key, val);
+    fn make_global(&mut self, slot:usize) -> () {
+        self.globals[slot] = true;
     }
 
-    fn get(&mut self, key:u32) -> Result<ScalarType, String> {
-        self.stack.reverse();
-        for scope in self.stack.iter_mut() {
-            if scope.contains_key(&key) {
-                let item = scope[&key].clone();
-                self.stack.reverse();
-                return Ok(item);
-            }
+    fn alloca(&mut self, slot:usize) -> () {
+        self.set(slot, ScalarType::None);
+    }
+
+    fn set(&mut self, slot:usize, val:ScalarType) {
+        let depth = self.scopes.len();
+        if depth == 1 && self.outermost_globals {
+            self.globals[slot] = true;
         }
-        Err(format!("Unknown memory {} referenced.", key))
+        if self.depths[slot] == depth {
+            self.values[slot] = val;
+            return;
+        }
+        let value = std::mem::replace(&mut self.values[slot], val);
+        self.saved.push(Saved { slot: slot, value: value, depth: self.depths[slot], live: true });
+        self.depths[slot] = depth;
     }
 
-    fn remove(&mut self, key:u32) -> Result<(), String> {
-        self.stack.reverse();
-        for scope in self.stack.iter_mut() {
-            if scope.contains_key(&key) {
-                scope.remove(&key);
-                self.stack.reverse();
-                return Ok(());
-            }
+    fn get(&self, slot:usize) -> Result<ScalarType, VmErrorKind> {
+        let depth = self.depths[slot];
+        if depth >= self.floor {
+            return Ok(self.values[slot].clone());
+        }
+        if depth == 0 || !self.globals[slot] {
+            return Err(VmErrorKind::UnknownMemory(self.resolver.id(slot)));
+        }
+        if depth == 1 {
+            return Ok(self.values[slot].clone());
         }
-        Err(format!("Tried to delete memory {} which does not exist.", key))
+        // A caller's scope hides the global, which it saved when it did.
+        return match self.saved.iter().rev().find(|saved| saved.live && saved.slot == slot && saved.depth == 1) {
+            Some(saved) => Ok(saved.value.clone()),
+            None => Err(VmErrorKind::UnknownMemory(self.resolver.id(slot))),
+        };
+    }
+
+    /// Looks a variable up by id rather than slot.
+    fn lookup(&self, id:u32) -> Result<ScalarType, VmErrorKind> {
+        return match self.resolver.slot(id) {
+            Some(slot) => self.get(slot),
+            None => Err(VmErrorKind::UnknownMemory(id)),
+        };
+    }
+
+    /// Drops the innermost binding of `slot`, uncovering the one it hid.
+    fn remove(&mut self, slot:usize) -> Result<(), VmErrorKind> {
+        let depth = self.depths[slot];
+        if depth == 0 || depth < self.floor && !(depth == 1 && self.globals[slot]) {
+            return Err(VmErrorKind::UnknownMemory(self.resolver.id(slot)));
+        }
+        let end = self.scopes.get(depth).copied().unwrap_or(self.saved.len());
+        let saved = self.saved[self.scopes[depth - 1]..end].iter_mut().rev()
+            .find(|saved| saved.live && saved.slot == slot)
+            .unwrap();
+        saved.live = false;
+        self.values[slot] = std::mem::replace(&mut saved.value, ScalarType::None);
+        self.depths[slot] = saved.depth;
+        return Ok(());
     }
 
     fn pop_scope(&mut self) -> () {
-        self.stack.pop();
+        let start = self.scopes.pop().unwrap_or(0);
+        for saved in self.saved.drain(start..).rev() {
+            if saved.live {
+                self.values[saved.slot] = saved.value;
+                self.depths[saved.slot] = saved.depth;
+            }
+        }
+        // Ending the outermost scope empties it rather than leaving nowhere to store variables.
+        if self.scopes.is_empty() {
+            self.scopes.push(0);
+        }
+        if self.scopes.len() < self.floor {
+            self.floor = self.floors.pop().unwrap_or(1);
+        }
+    }
+
+    fn depth(&self) -> usize {
+        return self.scopes.len();
+    }
+
+    /// Every binding made at `depth`, including ones hidden by an inner scope, by id.
+    fn bindings(&self, depth:usize) -> Vec<(u32, &ScalarType)> {
+        let current = (0..self.values.len()).filter(|slot| self.depths[*slot] == depth).map(|slot| (slot, &self.values[slot]));
+        let hidden = self.saved.iter().filter(|saved| saved.live && saved.depth == depth).map(|saved| (saved.slot, &saved.value));
+        let mut bindings:Vec<(u32, &ScalarType)> = current.chain(hidden).map(|(slot, val)| (self.resolver.id(slot), val)).collect();
+        bindings.sort_by_key(|(id, _)| *id);
+        return bindings;
     }
+
+    /// Drops every scope above `depth`.
+    fn truncate(&mut self, depth:usize) -> () {
+        while self.scopes.len() > depth {
+            self.pop_scope();
+        }
+    }
+}
+
+/// The result of a binary operator instruction on two values.
+fn operate(instr:&Instr, lhs:ScalarType, rhs:ScalarType) -> Result<ScalarType, VmErrorKind> {
+    return match instr {
+        Instr::Eq(..) => Ok(ScalarType::Bool(lhs == rhs)),
+        Instr::Neq(..) => Ok(ScalarType::Bool(lhs != rhs)),
+        Instr::Gt(..) => Ok(ScalarType::Bool(lhs > rhs)),
+        Instr::Gte(..) => Ok(ScalarType::Bool(lhs >= rhs)),
+        Instr::Lt(..) => Ok(ScalarType::Bool(lhs < rhs)),
+        Instr::Lte(..) => Ok(ScalarType::Bool(lhs <= rhs)),
+        Instr::Add(..) => lhs + rhs,
+        Instr::Sub(..) => lhs - rhs,
+        Instr::Mul(..) => lhs * rhs,
+        Instr::Div(..) => lhs / rhs,
+        Instr::Mod(..) => lhs % rhs,
+        Instr::Exp(..) => Ok(ScalarType::Float(lhs.pow(rhs)?)),
+        Instr::And(..) => lhs.logical("AND", rhs, |l0, r0| l0 && r0),
+        Instr::Or(..) => lhs.logical("OR", rhs, |l0, r0| l0 || r0),
+        Instr::Band(..) => lhs.bitwise("BAND", rhs, |l0, r0| l0 & r0),
+        Instr::Bor(..) => lhs.bitwise("BOR", rhs, |l0, r0| l0 | r0),
+        Instr::Bxor(..) => lhs.bitwise("BXOR", rhs, |l0, r0| l0 ^ r0),
+        Instr::Shl(..) => lhs.shift("SHL", rhs, i64::checked_shl),
+        Instr::Shr(..) => lhs.shift("SHR", rhs, i64::checked_shr),
+        Instr::Min(..) => lhs.pick("MIN", rhs, true),
+        Instr::Max(..) => lhs.pick("MAX", rhs, false),
+        _ => Err(VmErrorKind::MalformedInstruction(String::from("not a binary operator"))),
+    };
+}
+
+/// The value a literal instruction loads.
+fn literal(instr:&Instr) -> Option<ScalarType> {
+    return match instr {
+        Instr::Constant(_, Constant::Int(val)) => Some(ScalarType::Int(*val)),
+        Instr::Constant(_, Constant::Float(val)) => Some(ScalarType::Float(*val)),
+        Instr::Constant(_, Constant::Str(val)) => Some(ScalarType::Str(val.clone())),
+        Instr::Bool(_, val) => Some(ScalarType::Bool(*val)),
+        _ => None,
+    };
+}
+
+/// Evaluates the operator `instr` on literal instructions exactly as `Executor` would, giving the
+/// literal instruction that loads the result. `None` if the operator would fail at runtime, since
+/// folding it away would hide the error.
+pub(crate) fn fold(instr:&Instr, operands:&[&Instr]) -> Option<Instr> {
+    let values = operands.iter().map(|operand| literal(operand)).collect::<Option<Vec<ScalarType>>>()?;
+    let (cid, result) = match (instr, values.as_slice()) {
+        (Instr::Not(cid, _), [val]) => (*cid, val.clone().not()),
+        (Instr::Math(op, cid, _), [val]) => (*cid, val.clone().math(*op)),
+        (Instr::Eq(cid, ..) | Instr::Neq(cid, ..) | Instr::Gt(cid, ..) | Instr::Gte(cid, ..) | Instr::Lt(cid, ..)
+        | Instr::Lte(cid, ..) | Instr::Add(cid, ..) | Instr::Sub(cid, ..) | Instr::Mul(cid, ..) | Instr::Div(cid, ..)
+        | Instr::Mod(cid, ..) | Instr::Exp(cid, ..) | Instr::And(cid, ..) | Instr::Or(cid, ..) | Instr::Band(cid, ..)
+        | Instr::Bor(cid, ..) | Instr::Bxor(cid, ..) | Instr::Shl(cid, ..) | Instr::Shr(cid, ..) | Instr::Min(cid, ..)
+        | Instr::Max(cid, ..), [lhs, rhs]) => {
+            (*cid, operate(instr, lhs.clone(), rhs.clone()))
+        },
+        _ => return None,
+    };
+    return match result.ok()? {
+        ScalarType::Int(val) => Some(Instr::Constant(cid, Constant::Int(val))),
+        ScalarType::Float(val) => Some(Instr::Constant(cid, Constant::Float(val))),
+        ScalarType::Str(val) => Some(Instr::Constant(cid, Constant::Str(val))),
+        ScalarType::Bool(val) => Some(Instr::Bool(cid, val)),
+        _ => None,
+    };
+}
+
+/// How deeply CALLs may nest before the executor reports a stack overflow.
+pub const DEFAULT_MAX_CALL_DEPTH:usize = 1024;
+
+/// The most digits FMT_NUM puts after the decimal point.
+pub const MAX_FMT_NUM_PRECISION:usize = 64;
+
+struct CallFrame {
+    /// The instruction after the CALL in the caller.
+    return_to:usize,
+    result:usize,
+    /// Scope depth of the caller, restored when the call returns.
+    depth:usize,
+    function:u32
 }
 
-#[derive(Clone)]
-struct ByteCursor {
-    src:Box<ByteCode>,
-    cursor: i32
+/// A program being run one instruction at a time by `Executor::step`.
+struct Session {
+    instrs:Vec<Instr>,
+    origins:Vec<(usize, u32)>,
+    /// The index of the first instruction of each block, past any labels that follow its own.
+    blocks:HashMap<u32, usize>,
+    /// Whether START has been passed.
+    start:bool
 }
 
-impl ByteCursor {
-    fn new(src:Box<ByteCode>) -> ByteCursor {
-        return ByteCursor {
-            src: src,
-            cursor: -1
+pub struct Executor {
+    functions: Vec<Function>,
+    calls: Vec<CallFrame>,
+    max_call_depth: usize,
+    bytecode: Box<ByteCode>,
+    /// The next instruction to run.
+    pc: usize,
+    stack: ScopeStack,
+    /// Open files, indexed by the handle stored in `ScalarType::File`. Closed files leave a `None`.
+    files: Vec<Option<File>>,
+    /// Where STDOUT writes to and STDIN reads from.
+    io: Box<dyn Io>,
+    breakpoints: Vec<Breakpoint>,
+    debug_info: DebugInfo,
+    /// Set by the first `step` or `run_until_breakpoint`.
+    session: Option<Session>
+}
+
+impl Executor {
+    pub fn new(bytecode:Box<ByteCode>) -> Executor {
+        Executor {
+            functions: vec![],
+            calls: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            bytecode: bytecode,
+            pc: 0,
+            stack: ScopeStack::new(),
+            files: vec![],
+            io: Box::new(StdIo),
+            breakpoints: vec![],
+            debug_info: DebugInfo::new(),
+            session: None
         }
     }
 
-    fn finished(&self) -> bool {
-        return self.cursor+1 == self.src.len() as i32;
+    /// Creates an executor for a program in the binary `.bc` format.
+    pub fn from_bytes(bytes:&[u8]) -> Result<Executor, String> {
+        return Ok(Executor::new(Box::new(ByteCode::deserialize(bytes)?)));
     }
 
-    fn _next(&mut self) -> Result<ByteType, ()> {
-        if self.finished() {
-            self.cursor = -1;
-            return Err(());
-        }
-        self.cursor += 1;
+    /// Sets how deeply CALLs may nest, `DEFAULT_MAX_CALL_DEPTH` by default.
+    pub fn with_max_call_depth(mut self, depth:usize) -> Executor {
+        self.max_call_depth = depth;
+        return self;
+    }
 
-        return Ok(self.src.at(self.cursor as usize));
+    /// Runs STDOUT and STDIN against `io` instead of the process's standard streams.
+    pub fn with_io(mut self, io:Box<dyn Io>) -> Executor {
+        self.io = io;
+        return self;
     }
 
-    fn at(&mut self, index: usize) -> ByteType {
-        self.cursor = index as i32;
-        let ret = self.src.at(index);
-        return ret;
+    /// Gives back the executor's `Io`, for instance to reuse it for another program.
+    pub fn into_io(self) -> Box<dyn Io> {
+        return self.io;
     }
-    
-    fn set(&mut self, index: usize, item:u32) {
-        self.cursor = index as i32;
-        self.src.set(index, item);
+
+    pub fn run(&mut self) -> Result<(), VmError> {
+        // Decode everything up front so the loop below never looks at raw words.
+        let decoded = decode(&self.bytecode, &mut self.stack.resolver)?;
+        self.pc = 0;
+        return self.dispatch(decoded, false);
     }
 
-    fn prev(&mut self) -> ByteType {
-        self.cursor -= 1;
-        return self.src.at(self.cursor as usize);
+    /// The program as it stands, including anything added with `run_appended`.
+    pub fn bytecode(&self) -> &ByteCode {
+        return &self.bytecode;
     }
 
-    fn jump(&mut self, ind:u32) -> () {
-        self.cursor = ind as i32;
+    /// The value currently bound to `id`, shown as STDOUT would print it but with strings quoted,
+    /// or `None` if it is unbound.
+    pub fn variable(&self, id:u32) -> Option<String> {
+        return Some(self.stack.lookup(id).ok()?.render());
     }
 
-    fn current(&self) -> ByteType {
-        return self.src.at(self.cursor as usize);
+    /// Appends `code` to the program and runs just the new instructions, as if they came after
+    /// START. Variables, functions and open files from earlier runs are kept, so a program can be
+    /// fed in pieces; `code` has to take its ids from the program's pool, see
+    /// `BytecodeBuilder::continuing`. Variables bound outside any scope or call are globals, so
+    /// functions from later pieces see them. A piece that fails leaves no calls or scopes of its own open.
+    pub fn run_appended(&mut self, code:ByteCode) -> Result<(), VmError> {
+        return self.append(code, true);
+    }
+
+    /// Like `run_appended`, but runs `code` the way `run` runs a whole program: until its START,
+    /// only ALLOCA, literals, labels and functions take effect.
+    pub fn run_appended_program(&mut self, code:ByteCode) -> Result<(), VmError> {
+        return self.append(code, false);
     }
-}
 
-impl Iterator for ByteCursor {
-    type Item = ByteType;
+    fn append(&mut self, code:ByteCode, start:bool) -> Result<(), VmError> {
+        self.stack.outermost_globals = true;
+        let offset = self.bytecode.len();
+        self.bytecode.extend_from(code);
+        let decoded = decode(&self.bytecode, &mut self.stack.resolver)?;
+        self.pc = decoded.origins.iter().position(|(origin, _)| *origin >= offset).unwrap_or(decoded.instrs.len());
+        let depth = self.stack.depth();
+        let result = self.dispatch(decoded, start);
+        if result.is_err() {
+            self.calls.clear();
+            self.stack.truncate(depth);
+        }
+        return result;
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        return match self._next() {
-            Ok(_data) => Some(_data),
-            Err(_) => None,
+    /// Runs decoded instructions from `self.pc`, with `start` telling whether START has been passed.
+    fn dispatch(&mut self, decoded:Decoded, mut start:bool) -> Result<(), VmError> {
+        self.stack.grow();
+        self.functions = decoded.functions;
+
+        while let Some(instr) = decoded.instrs.get(self.pc) {
+            let index = self.pc;
+            self.pc += 1;
+            let result = match instr {
+                Instr::Start => {start = true; Ok(())},
+                _ if start => self.execute(instr),
+                Instr::Alloca(slot) | Instr::Constant(slot, _) => {self.stack.make_global(*slot); self.execute(instr)},
+                Instr::Block(_) | Instr::Func(_) => self.execute(instr),
+                // Everything else only runs once START is reached.
+                _ => Ok(())
+            };
+            if let Err(kind) = result {
+                let _ = self.io.flush();
+                let (offset, opcode) = decoded.origins[index];
+                return Err(VmError { kind: kind, offset: offset, opcode: opcode });
+            }
         }
+        // Flushing is best effort: no instruction is to blame, and STDOUT reports its own write errors.
+        let _ = self.io.flush();
+        return Ok(());
     }
-}
 
-pub struct Executor {
-    /// block, position
-    blocks: HashMap<u32, u32>,
-    bytecode: ByteCursor,
-    stack: ScopeStack
-}
+    /// Maps offsets to source lines so `Breakpoint::Line` and `line` work, see `Program::debug_info`.
+    pub fn with_debug_info(mut self, debug_info:DebugInfo) -> Executor {
+        self.debug_info = debug_info;
+        return self;
+    }
 
-impl Executor {
-    pub fn new(bytecode:Box<ByteCode>) -> Executor {
-        Executor {
-            blocks: HashMap::new(),
-            bytecode: ByteCursor::new(bytecode),
-            stack: ScopeStack::new()
+    pub fn add_breakpoint(&mut self, breakpoint:Breakpoint) {
+        if !self.breakpoints.contains(&breakpoint) {
+            self.breakpoints.push(breakpoint);
         }
     }
 
-    pub fn run(&mut self) {
-        let mut block_bytes = self.bytecode.clone();
-        while !block_bytes.finished() {
-            let byt = block_bytes._next().unwrap();
-            if byt == bc::BLOCK {
-                self.prerender_block(&mut block_bytes);
-            }
-        }
+    /// Returns whether the breakpoint was set.
+    pub fn remove_breakpoint(&mut self, breakpoint:Breakpoint) -> bool {
+        let count = self.breakpoints.len();
+        self.breakpoints.retain(|set| *set != breakpoint);
+        return self.breakpoints.len() != count;
+    }
 
-        self.bytecode.cursor = -1;
-
-        let mut start = false;
-
-        while !self.bytecode.finished() {
-            if let ByteType::Num(byt) = self.bytecode._next().unwrap() {
-                if start {
-                    match byt {
-                        bc::ENDL => {},
-                        bc::ALLOCA => self._alloca(),
-                        bc::STORE => self._store(),
-                        bc::DEL => self._del(),
-                        bc::EQ => self._eq(),
-                        bc::GT => self._gt(),
-                        bc::GTE => self._gte(),
-                        bc::LT => self._lt(),
-                        bc::LTE => self._lte(),
-                        bc::NEQ => self._neq(),
-                        bc::ADD => self._add(),
-                        bc::SUB => self._sub(),
-                        bc::MUL => self._mul(),
-                        bc::DIV => self._div(),
-                        bc::MOD => self._mod(),
-                        bc::EXP => self._exp(),
-                        bc::NUM => self._num(),
-                        bc::BOOL => self._bool(),
-                        bc::STR => self._str(),
-                        bc::FMT => self._fmt(),
-                        bc::STDOUT => self._stdout(),
-                        bc::STDIN => self._stdin(),
-                        bc::BEGIN_SCOPE => self.stack.new_scope(),
-                        bc::END_SCOPE => self.stack.pop_scope(),
-                        bc::BLOCK => self._block(),
-                        bc::JUMP => self._jump(),
-                        bc::COND_JUMP => self._cond_jump(),
-                        bc::CAST_STR => self._cast_str(),
-                        bc::CAST_NUM => self._cast_num(),
-                        bc::FMT_NUM => self._fmt_num(),
-                        _=> ()
-                    }
-                }
-                else {
-                    match byt {
-                        bc::ALLOCA => {self._alloca()},
-                        bc::NUM => {self._num()},
-                        bc::STR => {self._str()},
-                        bc::BLOCK => {self._block()},
-                        bc::START => {start = true},
-                        _ => ()
-                    }
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        return &self.breakpoints;
+    }
+
+    /// Runs the next instruction, starting the program on the first call. Labels, and everything
+    /// before START that only runs once START jumps back to it, are passed over rather than
+    /// stepped on. After an error the program counts as finished.
+    pub fn step(&mut self) -> Result<Pause, VmError> {
+        let mut session = self.session()?;
+        let result = self.step_session(&mut session);
+        self.session = Some(session);
+        return result;
+    }
+
+    /// Steps until the next instruction is at a breakpoint or the program ends. A breakpoint on
+    /// the instruction the program is paused at does not stop it again, except before the first step.
+    pub fn run_until_breakpoint(&mut self) -> Result<Pause, VmError> {
+        let fresh = self.session.is_none();
+        let mut session = self.session()?;
+        let result = if fresh && self.at_breakpoint(&session) {
+            Ok(Pause::Breakpoint)
+        } else {
+            loop {
+                match self.step_session(&mut session) {
+                    Ok(Pause::Step) if self.at_breakpoint(&session) => break Ok(Pause::Breakpoint),
+                    Ok(Pause::Step) => continue,
+                    result => break result,
                 }
             }
+        };
+        self.session = Some(session);
+        return result;
+    }
+
+    /// The offset of the instruction the next step runs, or `None` before the first step and
+    /// once the program has finished.
+    pub fn position(&self) -> Option<usize> {
+        let session = self.session.as_ref()?;
+        return session.origins.get(self.pc).map(|(offset, _)| *offset);
+    }
+
+    /// The source line of `position`, if the executor has debug info.
+    pub fn line(&self) -> Option<usize> {
+        return self.debug_info.line(self.position()?);
+    }
+
+    /// Every scope of the program, outermost first.
+    pub fn frames(&self) -> Vec<Frame> {
+        return (1..=self.stack.depth()).map(|depth| Frame {
+            depth: depth,
+            function: self.calls.iter().find(|call| call.depth + 1 == depth).map(|call| call.function),
+            variables: self.stack.bindings(depth).into_iter().map(|(id, val)| (id, val.render())).collect()
+        }).collect();
+    }
+
+    /// Takes the running session, or decodes the program to begin one.
+    fn session(&mut self) -> Result<Session, VmError> {
+        if let Some(session) = self.session.take() {
+            return Ok(session);
+        }
+        let decoded = decode(&self.bytecode, &mut self.stack.resolver)?;
+        self.stack.grow();
+        self.functions = decoded.functions;
+        self.pc = 0;
+        let mut blocks = HashMap::new();
+        let mut next = decoded.instrs.len();
+        for (index, instr) in decoded.instrs.iter().enumerate().rev() {
+            match instr {
+                Instr::Block(block) => {blocks.entry(*block).or_insert(next);},
+                _ => next = index,
+            }
         }
+        let session = Session { instrs: decoded.instrs, origins: decoded.origins, blocks: blocks, start: false };
+        self.skip_idle(&session);
+        return Ok(session);
     }
 
-    fn _next(&mut self) -> Result<u32, String> {
-        if let ByteType::Num(bcode) = self.bytecode._next().unwrap() {
-            return Ok(bcode);
+    fn step_session(&mut self, session:&mut Session) -> Result<Pause, VmError> {
+        let Some(instr) = session.instrs.get(self.pc) else {
+            return Ok(Pause::Finished);
+        };
+        let index = self.pc;
+        self.pc += 1;
+        let result = match instr {
+            Instr::Start => {session.start = true; Ok(())},
+            Instr::Alloca(slot) | Instr::Constant(slot, _) if !session.start => {self.stack.make_global(*slot); self.execute(instr)},
+            _ => self.execute(instr),
+        };
+        if let Err(kind) = result {
+            let _ = self.io.flush();
+            // Scopes and calls are left as they were so they can still be inspected.
+            self.pc = session.instrs.len();
+            let (offset, opcode) = session.origins[index];
+            return Err(VmError { kind: kind, offset: offset, opcode: opcode });
+        }
+        self.skip_idle(session);
+        if self.pc >= session.instrs.len() {
+            let _ = self.io.flush();
+            return Ok(Pause::Finished);
         }
-        return Err(String::from("Expected a u32"));
+        return Ok(Pause::Step);
     }
 
-    fn _next_str(&mut self) -> Result<String, String> {
-        if let ByteType::Str(bcode) = self.bytecode._next().unwrap() {
-            return Ok(bcode);
+    /// Moves past instructions that do nothing where they are, as `dispatch` would.
+    fn skip_idle(&mut self, session:&Session) {
+        while let Some(instr) = session.instrs.get(self.pc) {
+            let idle = match instr {
+                Instr::Block(_) => true,
+                Instr::Start | Instr::Alloca(_) | Instr::Constant(..) | Instr::Func(_) => false,
+                _ => !session.start,
+            };
+            if !idle {
+                return;
+            }
+            self.pc += 1;
         }
-        return Err(String::from("Expected a u32"));
     }
 
-    fn _block(&mut self) {
-        let block = self._next().unwrap();
-        self.blocks.insert(block, self.bytecode.cursor as u32);
-        self.bytecode._next().unwrap();
+    fn at_breakpoint(&self, session:&Session) -> bool {
+        let Some((offset, _)) = session.origins.get(self.pc) else {
+            return false;
+        };
+        return self.breakpoints.iter().any(|breakpoint| match breakpoint {
+            Breakpoint::Offset(at) => at == offset,
+            Breakpoint::Block(block) => session.blocks.get(block) == Some(&self.pc),
+            Breakpoint::Line(line) => self.debug_info.line(*offset) == Some(*line),
+        });
+    }
+
+    fn execute(&mut self, instr:&Instr) -> Result<(), VmErrorKind> {
+        return match instr {
+            Instr::Start | Instr::Block(_) => Ok(()),
+            Instr::Alloca(cid) => {self.stack.alloca(*cid); Ok(())},
+            Instr::Store(id, val) => self._store(*id, *val),
+            Instr::Del(id) => self.stack.remove(*id),
+            Instr::Eq(cid, lhs, rhs) | Instr::Neq(cid, lhs, rhs) | Instr::Gt(cid, lhs, rhs) | Instr::Gte(cid, lhs, rhs)
+            | Instr::Lt(cid, lhs, rhs) | Instr::Lte(cid, lhs, rhs) | Instr::Add(cid, lhs, rhs) | Instr::Sub(cid, lhs, rhs)
+            | Instr::Mul(cid, lhs, rhs) | Instr::Div(cid, lhs, rhs) | Instr::Mod(cid, lhs, rhs) | Instr::Exp(cid, lhs, rhs)
+            | Instr::And(cid, lhs, rhs) | Instr::Or(cid, lhs, rhs) | Instr::Band(cid, lhs, rhs) | Instr::Bor(cid, lhs, rhs)
+            | Instr::Bxor(cid, lhs, rhs) | Instr::Shl(cid, lhs, rhs) | Instr::Shr(cid, lhs, rhs) | Instr::Min(cid, lhs, rhs)
+            | Instr::Max(cid, lhs, rhs) => {
+                self.binary(instr, *cid, *lhs, *rhs)
+            },
+            Instr::Not(cid, val) => self._not(*cid, *val),
+            Instr::Math(op, cid, val) => self._math(*op, *cid, *val),
+            Instr::Constant(cid, val) => self._constant(*cid, val),
+            Instr::Bool(cid, val) => {self.stack.set(*cid, ScalarType::Bool(*val)); Ok(())},
+            Instr::Fmt(cid, string, args) => self._fmt(*cid, *string, args),
+            Instr::Stdout(msg) => self._stdout(*msg),
+            Instr::Stdin(cid) => self._stdin(*cid),
+            Instr::BeginScope => {self.stack.new_scope(); Ok(())},
+            Instr::EndScope => {self.stack.pop_scope(); Ok(())},
+            Instr::Jump(block, target) => self._jump(*block, *target),
+            Instr::CondJump(block, cond, target) => self._cond_jump(*block, *cond, *target),
+            Instr::CastStr(cid, item) => self._cast_str(*cid, *item),
+            Instr::CastNum(cid, item) => self._cast_num(*cid, *item),
+            Instr::CastInt(cid, item) => self._cast_int(*cid, *item),
+            Instr::CastFloat(cid, item) => self._cast_float(*cid, *item),
+            Instr::FmtNum(cid, num, precision) => self._fmt_num(*cid, *num, *precision),
+            Instr::Open(cid, path, mode) => self._open(*cid, *path, *mode),
+            Instr::Close(id) => self._close(*id),
+            Instr::Read(cid, id) => self._read(*cid, *id),
+            Instr::Write(id, val) => self._write(*id, *val),
+            Instr::List(cid, items) => self._list(*cid, items),
+            Instr::Index(cid, list, indices) => self._index(*cid, *list, indices),
+            Instr::StoreIndex(list, item, indices) => self._store_index(*list, *item, indices),
+            Instr::Push(list, item) => self._push(*list, *item),
+            Instr::Pop(cid, list) => self._pop(*cid, *list),
+            Instr::Func(func) => self._func(*func),
+            Instr::Call(cid, func, target, args) => self._call(*cid, *func, *target, args),
+            Instr::Ret(value) => self._ret(*value),
+            Instr::End => self._return(ScalarType::None),
+        };
     }
 
-    fn prerender_block(&mut self, mut bytecode:&mut ByteCursor) {
-        if let ByteType::Num(block) = bytecode._next().unwrap() {
-            self.blocks.insert(block, bytecode.cursor as u32);
-            bytecode._next().unwrap();
+    /// Runs one of the binary operators, which all take `cid lhs rhs`.
+    fn binary(&mut self, instr:&Instr, cid:usize, lhs:usize, rhs:usize) -> Result<(), VmErrorKind> {
+        let lhs = self.stack.get(lhs)?;
+        let rhs = self.stack.get(rhs)?;
+        self.stack.set(cid, operate(instr, lhs, rhs)?);
+        return Ok(());
+    }
+
+    /// Skips over the body of a function that execution fell into.
+    fn _func(&mut self, func:usize) -> Result<(), VmErrorKind> {
+        self.pc = self.functions[func].end + 1;
+        return Ok(());
+    }
+
+    fn _call(&mut self, cid:usize, func:u32, target:Option<usize>, args:&[usize]) -> Result<(), VmErrorKind> {
+        let function = &self.functions[target.ok_or(VmErrorKind::MissingFunction(func))?];
+        if function.params.len() != args.len() {
+            return Err(VmErrorKind::MalformedInstruction(format!(
+                "function {} expects {} arguments, found {}", func, function.params.len(), args.len()
+            )));
+        }
+        if self.calls.len() >= self.max_call_depth {
+            return Err(VmErrorKind::StackOverflow(self.max_call_depth));
+        }
+        let params = function.params.clone();
+        let body = function.body;
+        let mut values = vec![];
+        for arg in args {
+            values.push(self.stack.get(*arg)?);
+        }
+        self.calls.push(CallFrame { return_to: self.pc, result: cid, depth: self.stack.depth(), function: func });
+        self.stack.new_frame();
+        for (param, value) in params.into_iter().zip(values) {
+            self.stack.set(param, value);
         }
+        self.pc = body;
+        return Ok(());
     }
 
-    fn _alloca(&mut self) {
-        let cid = self._next().unwrap();
-        self.stack.alloca(cid);
-        self.bytecode._next().unwrap();
+    fn _ret(&mut self, value:Option<usize>) -> Result<(), VmErrorKind> {
+        let value = match value {
+            Some(id) => self.stack.get(id)?,
+            None => ScalarType::None,
+        };
+        return self._return(value);
     }
 
-    fn _store(&mut self) {
-        let id = self._next().unwrap();
-        let _val = self._next().unwrap();
-        let val = self.stack.get(_val).unwrap();
+    /// Leaves the innermost call, handing `value` to the caller.
+    fn _return(&mut self, value:ScalarType) -> Result<(), VmErrorKind> {
+        let frame = self.calls.pop()
+            .ok_or_else(|| VmErrorKind::MalformedInstruction(String::from("returned while not inside a function")))?;
+        self.stack.truncate(frame.depth);
+        self.stack.set(frame.result, value);
+        self.pc = frame.return_to;
+        return Ok(());
+    }
+
+    fn _store(&mut self, id:usize, _val:usize) -> Result<(), VmErrorKind> {
+        let val = self.stack.get(_val)?;
         self.stack.set(id, val);
-        
-        self.bytecode._next().unwrap();
-    }
-
-    fn _del(&mut self) {
-        let id = self._next().unwrap();
-        self.stack.remove(id);
-        self.bytecode._next().unwrap();
-    }
-
-    fn _eq(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Bool(lhs == rhs));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _gt(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Bool(lhs > rhs));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _lt(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Bool(lhs < rhs));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _gte(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Bool(lhs >= rhs));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _lte(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Bool(lhs <= rhs));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _neq(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Bool(lhs != rhs));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _add(&mut self) {
-        let cid = self._next().unwrap();
-        
-        let _lhs = self._next().unwrap();
-        
-        let lhs = self.stack.get(_lhs).unwrap();
-        
-        let _rhs = self._next().unwrap();
-        
-        let rhs = self.stack.get(_rhs).unwrap();
-
-        self.stack.set(cid, lhs + rhs);
-        self.bytecode._next().unwrap();
-    }
-
-    fn _sub(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, lhs - rhs);
-        self.bytecode._next().unwrap();
-    }
-
-    fn _mul(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, lhs * rhs);
-        self.bytecode._next().unwrap();
-    }
-
-    fn _div(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, lhs / rhs);
-        self.bytecode._next().unwrap();
-    }
-
-    fn _mod(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, lhs % rhs);
-        self.bytecode._next().unwrap();
-    }
-
-    fn _exp(&mut self) {
-        let cid = self._next().unwrap();
-        let _lhs = self._next().unwrap();
-        let mut lhs = self.stack.get(_lhs).unwrap();
-        let _rhs = self._next().unwrap();
-        let rhs = self.stack.get(_rhs).unwrap();
-        self.stack.set(cid, ScalarType::Float(lhs.pow(rhs)));
-        self.bytecode._next().unwrap();
-    }
-
-    fn _stdin(&mut self) {
-        let cid = self._next().unwrap();
-        let mut inp = String::new();
-        stdin().read_line(&mut inp).unwrap();
+        return Ok(());
+    }
+
+    fn _stdin(&mut self, cid:usize) -> Result<(), VmErrorKind> {
+        let mut inp = self.io.read_line().map_err(|err| VmErrorKind::Io(err.to_string()))?;
         if let Some('\n') = inp.chars().next_back() {
             inp.pop();
         }
@@ -634,188 +1093,794 @@ impl Executor {
             inp.pop();
         }
         self.stack.set(cid, ScalarType::Str(inp));
-        self.bytecode._next().unwrap();
+        return Ok(());
     }
 
-    fn _stdout(&mut self) {
-        let _msg = self._next().unwrap();
-        match self.stack.get(_msg).unwrap() {
-            ScalarType::Str(msg) => {
-                print!("{}", msg);
-            },
-            ScalarType::Int(msg) => {
-                print!("{}", msg);
-            },
-            ScalarType::Bool(msg) => {
-                print!("{}", if (msg){ "true" } else { "false" });
-            },
-            ScalarType::Float(msg) => {
-                print!("{}", msg);
-            },
-            ScalarType::None => {
-                print!("Null");
-            }
-        }
-        self.bytecode._next().unwrap();
+    fn _not(&mut self, cid:usize, val:usize) -> Result<(), VmErrorKind> {
+        let val = self.stack.get(val)?;
+        self.stack.set(cid, val.not()?);
+        return Ok(());
     }
 
-    fn _num(&mut self) {
-        let cid = self._next().unwrap();
-        let mut num = String::new();
-        let mut byt = self._next().unwrap();
-        while byt != bc::ENDL {
-            match byt {
-                bc::NEGATIVE => {
-                    num.push('-');
-                }
-                bc::DOT => {
-                    num.push('.');
-                }
-                _ => {
-                    num += format!("{}", byt).as_str();
-                }
-            }
-            byt = self._next().unwrap();
-        }
-        
-        self.stack.set(cid, ScalarType::Float(num.parse::<f32>().unwrap()));
+    fn _math(&mut self, op:MathOp, cid:usize, val:usize) -> Result<(), VmErrorKind> {
+        let val = self.stack.get(val)?;
+        self.stack.set(cid, val.math(op)?);
+        return Ok(());
     }
 
-    fn _bool(&mut self) {
-        let cid = self._next().unwrap();
-        let tf = self._next().unwrap();
-        
-        self.stack.set(cid, ScalarType::Bool(if tf == 1 {true} else {false}));
-        self.bytecode._next().unwrap();
+    fn _stdout(&mut self, _msg:usize) -> Result<(), VmErrorKind> {
+        let msg = self.stack.get(_msg)?;
+        self.io.write(msg.to_string().as_bytes()).map_err(|err| VmErrorKind::Io(err.to_string()))?;
+        return Ok(());
     }
 
-    fn _str(&mut self) {
-        let cid = self._next().unwrap();
-        let _str = self._next_str().unwrap();
-        self.stack.set(cid, ScalarType::Str(_str));
-        self.bytecode._next().unwrap();
-    }
-    
-    fn _cast_num(&mut self) {
-        let cid = self._next().unwrap();
-        let num_id = self._next().unwrap();
-        if let ScalarType::Str(f) = self.stack.get(num_id).unwrap() {
-            self.stack.set(cid, ScalarType::Float(f.parse::<u32>().unwrap() as f32));
-        }
-        self.bytecode._next().unwrap();
-    }
-
-    fn _fmt_num(&mut self) {
-        let cid = self._next().unwrap();
-        let _num = self._next().unwrap();
-        if let ScalarType::Float(num) = self.stack.get(_num).unwrap() {
-        let _precision = self._next().unwrap();
-        if let ScalarType::Float(precision) = self.stack.get(_precision).unwrap() {
-            if precision == 0.0 {
-                self.stack.set(cid, ScalarType::Str(format!("{}", num as i32)));
-            }
-            else {
-                self.stack.set(cid, ScalarType::Str(format!("{:.prec$}", num, prec = precision as usize)));
-            }
-        }}
-        self.bytecode._next().unwrap();
+    /// Loads a STR, NUM or INT literal.
+    fn _constant(&mut self, cid:usize, val:&Constant) -> Result<(), VmErrorKind> {
+        let val = match val {
+            Constant::Int(val) => ScalarType::Int(*val),
+            Constant::Float(val) => ScalarType::Float(*val),
+            Constant::Str(val) => ScalarType::Str(val.clone()),
+        };
+        self.stack.set(cid, val);
+        return Ok(());
     }
 
-    fn _cast_str(&mut self) {
-        let cid = self._next().unwrap();
-        let item = self._next().unwrap();
-        match self.stack.get(item).unwrap() {
-            ScalarType::Int(_val) => {
-                self.stack.set(cid, ScalarType::Str(format!("{}", _val)));
-            },
-            ScalarType::Float(_val) => {
-                self.stack.set(cid, ScalarType::Str(format!("{}", _val)));
-            },
-            ScalarType::Bool(_val) => {
-                self.stack.set(cid, ScalarType::Str(format!("{}", if _val {"true"} else {"false"})));
+    fn _cast_num(&mut self, cid:usize, num_id:usize) -> Result<(), VmErrorKind> {
+        match self.stack.get(num_id)? {
+            ScalarType::Str(f) => {
+                let val = f.trim().parse::<f64>()
+                    .map_err(|_| VmErrorKind::TypeMismatch(format!("cannot convert \"{}\" to a number", f)))?;
+                self.stack.set(cid, ScalarType::Float(val));
             },
-            ScalarType::None => {
-                self.stack.set(cid, ScalarType::Str(String::from("Null")));
-            },
-            ScalarType::Str(_val) => {},
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot CAST_NUM {}", other.type_name()))),
         }
-        self.bytecode._next().unwrap();
+        return Ok(());
+    }
+
+    fn _cast_int(&mut self, cid:usize, item:usize) -> Result<(), VmErrorKind> {
+        let val = match self.stack.get(item)? {
+            ScalarType::Int(val) => val,
+            // Truncates toward zero, like `as` but refusing values an int cannot hold.
+            ScalarType::Float(val) if val.is_finite() && val.trunc() >= i64::MIN as f64 && val.trunc() < i64::MAX as f64 => val as i64,
+            ScalarType::Bool(val) => val as i64,
+            ScalarType::Str(val) => val.trim().parse::<i64>()
+                .map_err(|_| VmErrorKind::TypeMismatch(format!("cannot convert \"{}\" to an int", val)))?,
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot convert {} to an int", other))),
+        };
+        self.stack.set(cid, ScalarType::Int(val));
+        return Ok(());
     }
 
-    fn dyn_format(src:String, mut arguments:Vec<String>) -> String {
+    fn _cast_float(&mut self, cid:usize, item:usize) -> Result<(), VmErrorKind> {
+        let val = match self.stack.get(item)? {
+            ScalarType::Int(val) => val as f64,
+            ScalarType::Float(val) => val,
+            ScalarType::Bool(val) => val as i64 as f64,
+            ScalarType::Str(val) => val.trim().parse::<f64>()
+                .map_err(|_| VmErrorKind::TypeMismatch(format!("cannot convert \"{}\" to a float", val)))?,
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot CAST_FLOAT {}", other.type_name()))),
+        };
+        self.stack.set(cid, ScalarType::Float(val));
+        return Ok(());
+    }
+
+    fn _fmt_num(&mut self, cid:usize, _num:usize, _precision:usize) -> Result<(), VmErrorKind> {
+        let (num, precision) = (self.stack.get(_num)?, self.stack.get(_precision)?);
+        let digits = match precision {
+            ScalarType::Int(digits) => digits as f64,
+            ScalarType::Float(digits) => digits,
+            _ => return Err(illegal("FMT_NUM", &num, &precision)),
+        };
+        if digits.fract() != 0.0 || !(0.0..=MAX_FMT_NUM_PRECISION as f64).contains(&digits) {
+            return Err(VmErrorKind::TypeMismatch(format!(
+                "FMT_NUM precision must be a whole number from 0 to {}, found {}", MAX_FMT_NUM_PRECISION, precision
+            )));
+        }
+        let digits = digits as usize;
+        let formatted = match num {
+            ScalarType::Int(num) if digits == 0 => format!("{}", num),
+            ScalarType::Int(num) => format!("{:.prec$}", num as f64, prec = digits),
+            ScalarType::Float(num) if digits == 0 => format!("{}", num as i64),
+            ScalarType::Float(num) => format!("{:.prec$}", num, prec = digits),
+            _ => return Err(illegal("FMT_NUM", &num, &precision)),
+        };
+        self.stack.set(cid, ScalarType::Str(formatted));
+        return Ok(());
+    }
+
+    fn _cast_str(&mut self, cid:usize, item:usize) -> Result<(), VmErrorKind> {
+        let string = self.stack.get(item)?.to_string();
+        self.stack.set(cid, ScalarType::Str(string));
+        return Ok(());
+    }
+
+    /// Substitutes `{}` placeholders in order; `{{` and `}}` produce literal braces.
+    fn dyn_format(src:String, mut arguments:Vec<String>) -> Result<String, VmErrorKind> {
         arguments.reverse();
         let mut ret_str = String::new();
-        for c in src.chars() {
-            let mut formatting = false;
-            match c {
-                '{' => {
-                    formatting = !formatting;
-                    if !formatting {
-                        ret_str.push('{');
-                    }
+        let mut chars = src.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    ret_str.push(c);
                 }
-                '}' => {
-                    if !formatting {
-                        ret_str.push('}');
-                    }
-                    else {
-                        ret_str += arguments.pop().expect("Incorrect number of arguments for format string.").as_str();
-                    }
-                    formatting = false;
+                ('{', Some('}')) => {
+                    chars.next();
+                    ret_str += arguments.pop()
+                        .ok_or_else(|| VmErrorKind::MalformedInstruction(String::from("incorrect number of arguments for format string")))?
+                        .as_str();
                 }
                 _ => {
                     ret_str.push(c);
                 }
             }
         }
-        return ret_str;
-    }
-
-    fn _fmt(&mut self) {
-        let cid = self._next().unwrap();
-        let __string = self._next().unwrap();
-        if let ScalarType::Str(_string) = self.stack.get(__string).unwrap() {
-            let mut fmt_args = vec![];
-            let mut byt = self._next().unwrap();
-            while byt != bc::ENDL {
-                match self.stack.get(byt).unwrap() {
-                    ScalarType::Int(_val) => {
-                        fmt_args.push(format!("{}", _val));
-                    },
-                    ScalarType::Float(_val) => {
-                        fmt_args.push(format!("{}", _val));
-                    },
-                    ScalarType::Bool(_val) => {
-                        fmt_args.push(format!("{}", _val));
-                    },
-                    ScalarType::Str(_val) => {
-                        fmt_args.push(_val);
-                    },
-                    ScalarType::None => {
-                        fmt_args.push(String::from("Null"));
-                    },
+        return Ok(ret_str);
+    }
+
+    fn _fmt(&mut self, cid:usize, __string:usize, args:&[usize]) -> Result<(), VmErrorKind> {
+        match self.stack.get(__string)? {
+            ScalarType::Str(_string) => {
+                let mut fmt_args = vec![];
+                for arg in args {
+                    fmt_args.push(self.stack.get(*arg)?.to_string());
                 }
-                byt = self._next().unwrap();
-            }
-            
-            self.stack.set(cid, ScalarType::Str(Self::dyn_format(_string, fmt_args)));
+
+                self.stack.set(cid, ScalarType::Str(Self::dyn_format(_string, fmt_args)?));
+            },
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot FMT with a {} format string", other.type_name()))),
+        }
+        return Ok(());
+    }
+
+    fn _open(&mut self, cid:usize, _path:usize, _mode:usize) -> Result<(), VmErrorKind> {
+        let (path, mode) = match (self.stack.get(_path)?, self.stack.get(_mode)?) {
+            (ScalarType::Str(path), ScalarType::Str(mode)) => (path, mode),
+            (path, mode) => return Err(illegal("OPEN", &path, &mode)),
+        };
+        let mut options = OpenOptions::new();
+        match mode.as_str() {
+            "r" | "read" => options.read(true),
+            "w" | "write" => options.write(true).create(true).truncate(true),
+            "a" | "append" => options.append(true).create(true),
+            _ => return Err(VmErrorKind::Io(format!("unknown file mode `{}`, expected r, w or a", mode))),
+        };
+        let file = options.open(&path).map_err(|err| VmErrorKind::Io(format!("cannot open `{}`: {}", path, err)))?;
+        self.files.push(Some(file));
+        self.stack.set(cid, ScalarType::File(self.files.len() - 1));
+        return Ok(());
+    }
+
+    /// Resolves a variable holding an open file to its entry in the handle table.
+    fn file(&mut self, id:usize) -> Result<&mut File, VmErrorKind> {
+        return match self.stack.get(id)? {
+            ScalarType::File(handle) => match self.files.get_mut(handle) {
+                Some(Some(file)) => Ok(file),
+                _ => Err(VmErrorKind::Io(format!("file handle {} is closed", handle))),
+            },
+            other => Err(VmErrorKind::TypeMismatch(format!("expected a file, found {}", other.type_name()))),
+        };
+    }
+
+    fn _close(&mut self, id:usize) -> Result<(), VmErrorKind> {
+        self.file(id)?;
+        if let ScalarType::File(handle) = self.stack.get(id)? {
+            self.files[handle] = None;
         }
+        return Ok(());
+    }
+
+    fn _read(&mut self, cid:usize, id:usize) -> Result<(), VmErrorKind> {
+        let mut contents = String::new();
+        self.file(id)?.read_to_string(&mut contents).map_err(|err| VmErrorKind::Io(err.to_string()))?;
+        self.stack.set(cid, ScalarType::Str(contents));
+        return Ok(());
     }
 
-    fn _jump(&mut self) {
-        let block = self._next().unwrap();
-        self.bytecode.jump(self.blocks[&block]);
+    fn _write(&mut self, id:usize, _val:usize) -> Result<(), VmErrorKind> {
+        let val = self.stack.get(_val)?.to_string();
+        self.file(id)?.write_all(val.as_bytes()).map_err(|err| VmErrorKind::Io(err.to_string()))?;
+        return Ok(());
     }
 
-    fn _cond_jump(&mut self) {
-        let block = self._next().unwrap();
-        let _cond = self._next().unwrap();
-        if let ScalarType::Bool(cond) = self.stack.get(_cond).unwrap() {
-            if cond {
-                self.bytecode.jump(self.blocks[&block]);
-            } else {
-                self.bytecode._next().unwrap();
+    fn _list(&mut self, cid:usize, ids:&[usize]) -> Result<(), VmErrorKind> {
+        let mut items = vec![];
+        for id in ids {
+            items.push(self.stack.get(*id)?);
+        }
+        self.stack.set(cid, ScalarType::List(Rc::new(RefCell::new(items))));
+        return Ok(());
+    }
+
+    /// Looks up `indices` one dimension at a time, starting from `value`.
+    fn index_into(&mut self, mut value:ScalarType, indices:&[usize]) -> Result<ScalarType, VmErrorKind> {
+        for id in indices {
+            let index = self.stack.get(*id)?;
+            value = match value {
+                ScalarType::List(items) => {
+                    let items = items.borrow();
+                    items[list_index(&index, items.len())?].clone()
+                },
+                other => return Err(VmErrorKind::TypeMismatch(format!("cannot index into {}", other.type_name()))),
+            };
+        }
+        return Ok(value);
+    }
+
+    fn _index(&mut self, cid:usize, list:usize, indices:&[usize]) -> Result<(), VmErrorKind> {
+        if indices.is_empty() {
+            return Err(VmErrorKind::MalformedInstruction(String::from("INDEX expects at least one index")));
+        }
+        let list = self.stack.get(list)?;
+        let item = self.index_into(list, indices)?;
+        self.stack.set(cid, item);
+        return Ok(());
+    }
+
+    fn _store_index(&mut self, list:usize, _item:usize, indices:&[usize]) -> Result<(), VmErrorKind> {
+        let Some((last, path)) = indices.split_last() else {
+            return Err(VmErrorKind::MalformedInstruction(String::from("STORE_INDEX expects at least one index")));
+        };
+        let item = self.stack.get(_item)?;
+        let list = self.stack.get(list)?;
+        match self.index_into(list, path)? {
+            ScalarType::List(items) => {
+                let index = self.stack.get(*last)?;
+                let mut items = items.borrow_mut();
+                let index = list_index(&index, items.len())?;
+                items[index] = item;
+            },
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot index into {}", other.type_name()))),
+        }
+        return Ok(());
+    }
+
+    fn _push(&mut self, list:usize, _item:usize) -> Result<(), VmErrorKind> {
+        let item = self.stack.get(_item)?;
+        match self.stack.get(list)? {
+            ScalarType::List(items) => items.borrow_mut().push(item),
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot PUSH onto {}", other.type_name()))),
+        }
+        return Ok(());
+    }
+
+    fn _pop(&mut self, cid:usize, list:usize) -> Result<(), VmErrorKind> {
+        let top = match self.stack.get(list)? {
+            ScalarType::List(items) => items.borrow_mut().pop().ok_or(VmErrorKind::IndexOutOfBounds(-1, 0))?,
+            other => return Err(VmErrorKind::TypeMismatch(format!("cannot POP from {}", other.type_name()))),
+        };
+        self.stack.set(cid, top);
+        return Ok(());
+    }
+
+    fn _jump(&mut self, block:u32, target:Option<usize>) -> Result<(), VmErrorKind> {
+        self.pc = target.ok_or(VmErrorKind::MissingBlock(block))?;
+        return Ok(());
+    }
+
+    fn _cond_jump(&mut self, block:u32, _cond:usize, target:Option<usize>) -> Result<(), VmErrorKind> {
+        match self.stack.get(_cond)? {
+            ScalarType::Bool(true) => return self._jump(block, target),
+            ScalarType::Bool(false) => return Ok(()),
+            other => return Err(VmErrorKind::TypeMismatch(format!("COND_JUMP expects a bool condition, found {}", other.type_name()))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::lexer::asm::Parser;
+    use crate::vm::bytecodes::{self as bc, BytecodeBuilder};
+    use crate::vm::error::{VmError, VmErrorKind};
+    use crate::vm::io::BufferIo;
+    use crate::vm::vm::{Executor, ScalarType};
+
+    #[test]
+    fn vm_test_division_by_zero() {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        let num = bb.write_num(7.0, None);
+        let zero = bb.write_num(0.0, None);
+        let offset = bb.src.len();
+        bb.write_div(num, zero, None);
+        let err = Executor::new(bb.src).run().unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::DivisionByZero);
+        assert_eq!(err.opcode, bc::DIV);
+        assert_eq!(err.offset, offset);
+    }
+
+    #[test]
+    fn vm_test_unknown_memory() {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        bb.write_stdout(0x999);
+        let err = Executor::new(bb.src).run().unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::UnknownMemory(0x999));
+        assert_eq!(err.opcode, bc::STDOUT);
+    }
+
+    #[test]
+    fn vm_test_type_mismatch() {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        let string = bb.write_str(String::from("text"), None);
+        let truth = bb.write_bool(true, None);
+        bb.write_mul(string, truth, None);
+        let err = Executor::new(bb.src).run().unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot MUL str and bool")));
+        assert_eq!(err.opcode, bc::MUL);
+    }
+
+    #[test]
+    fn vm_test_fmt_num_precision() {
+        let fmt = |precision:&str| run_source(&format!("START\nNUM num 2\n{}\nFMT_NUM out num precision\n", precision), 1, "out");
+        assert!(fmt("NUM precision 2").unwrap() == ScalarType::Str(String::from("2.00")));
+        assert!(fmt("INT precision 64").is_ok());
+        for precision in ["NUM precision -1", "INT precision -1", "NUM precision 1.5", "NUM precision 1e12", "INT precision 65"] {
+            let err = fmt(precision).unwrap_err();
+            assert!(matches!(err.kind, VmErrorKind::TypeMismatch(_)), "{}: {}", precision, err);
+            assert_eq!(err.opcode, bc::FMT_NUM);
+        }
+    }
+
+    #[test]
+    fn vm_test_missing_block() {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        bb.write_jump(0x999);
+        let err = Executor::new(bb.src).run().unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::MissingBlock(0x999));
+        assert_eq!(err.opcode, bc::JUMP);
+    }
+
+    fn list_program(build:impl FnOnce(&mut BytecodeBuilder) -> u32) -> Result<ScalarType, VmError> {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        let result = build(&mut bb);
+        let mut exec = Executor::new(bb.src);
+        exec.run()?;
+        return Ok(exec.stack.lookup(result).unwrap());
+    }
+
+    #[test]
+    fn vm_test_list_index() {
+        let value = list_program(|bb| {
+            let one = bb.write_num(1.0, None);
+            let two = bb.write_num(2.0, None);
+            let name = bb.write_str(String::from("a"), None);
+            let inner = bb.write_list(vec![one, two], None);
+            let outer = bb.write_list(vec![inner, name], None);
+            let zero = bb.write_num(0.0, None);
+            let last = bb.write_num(-1.0, None);
+            bb.write_index(outer, vec![zero, last], None)
+        }).unwrap();
+        assert!(value == ScalarType::Float(2.0));
+    }
+
+    #[test]
+    fn vm_test_list_store_push_pop() {
+        let value = list_program(|bb| {
+            let one = bb.write_num(1.0, None);
+            let two = bb.write_num(2.0, None);
+            let zero = bb.write_num(0.0, None);
+            let inner = bb.write_list(vec![one], None);
+            let grid = bb.write_list(vec![inner], None);
+            bb.write_store_index(grid, two, vec![zero, zero]);
+            bb.write_push(inner, one);
+            bb.write_push(inner, two);
+            bb.write_pop(inner, None);
+            grid
+        }).unwrap();
+        assert_eq!(value.to_string(), "[[2, 1]]");
+    }
+
+    #[test]
+    fn vm_test_list_errors() {
+        let err = list_program(|bb| {
+            let list = bb.write_list(vec![], None);
+            let index = bb.write_num(3.0, None);
+            bb.write_index(list, vec![index], None)
+        }).unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::IndexOutOfBounds(3, 0));
+        assert_eq!(err.opcode, bc::INDEX);
+
+        let err = list_program(|bb| {
+            let one = bb.write_num(1.0, None);
+            let list = bb.write_list(vec![one], None);
+            let index = bb.write_num(-2.0, None);
+            bb.write_store_index(list, one, vec![index]);
+            list
+        }).unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::IndexOutOfBounds(-2, 1));
+
+        let err = list_program(|bb| {
+            let list = bb.write_list(vec![], None);
+            bb.write_pop(list, None)
+        }).unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::IndexOutOfBounds(-1, 0));
+
+        let err = list_program(|bb| {
+            let one = bb.write_num(1.0, None);
+            let list = bb.write_list(vec![one], None);
+            let half = bb.write_num(0.5, None);
+            bb.write_index(list, vec![half], None)
+        }).unwrap_err();
+        assert!(matches!(err.kind, VmErrorKind::TypeMismatch(_)));
+    }
+
+    /// Assembles and runs `src`, returning the value left in `name`.
+    fn run_source(src:&str, depth:usize, name:&str) -> Result<ScalarType, VmError> {
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let mut exec = Executor::new(program.bytecode).with_max_call_depth(depth);
+        exec.run()?;
+        return Ok(exec.stack.lookup(program.symbols[name]).unwrap());
+    }
+
+    #[test]
+    fn vm_test_scopes() {
+        let src = "
+            START
+                NUM x 1
+                NUM y 10
+                NUM z 5
+                LIST seen
+                BEGIN_SCOPE
+                    NUM x 2
+                    PUSH seen x
+                    ADD y y x
+                    PUSH seen y
+                    BEGIN_SCOPE
+                        NUM x 3
+                        DEL x
+                        PUSH seen x
+                        DEL z
+                    END_SCOPE
+                    DEL x
+                    PUSH seen x
+                END_SCOPE
+        ";
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let mut exec = Executor::new(program.bytecode);
+        exec.run().unwrap();
+        // Bindings made inside a scope hide the outer ones until the scope ends or they are deleted.
+        let seen:Vec<String> = match exec.stack.lookup(program.symbols["seen"]).unwrap() {
+            ScalarType::List(items) => items.borrow().iter().map(|item| item.to_string()).collect(),
+            other => panic!("expected a list, found {}", other),
+        };
+        assert_eq!(seen, vec!["2", "12", "2", "1"]);
+        assert!(exec.stack.lookup(program.symbols["x"]).unwrap() == ScalarType::Float(1.0));
+        assert!(exec.stack.lookup(program.symbols["y"]).unwrap() == ScalarType::Float(10.0));
+        // Deleting an outer variable from an inner scope is not undone when the scope ends.
+        assert_eq!(exec.stack.lookup(program.symbols["z"]).unwrap_err(), VmErrorKind::UnknownMemory(program.symbols["z"]));
+        assert_eq!(exec.stack.depth(), 1);
+
+        let err = run_source("START\nNUM x 1\nBEGIN_SCOPE\nNUM y 2\nEND_SCOPE\nSTDOUT y\n", 1, "x").unwrap_err();
+        assert!(matches!(err.kind, VmErrorKind::UnknownMemory(_)), "{}", err);
+        assert_eq!(err.opcode, bc::STDOUT);
+    }
+
+    const FACTORIAL:&str = "
+        FUNC factorial n
+            NUM one 1
+            LTE done n one
+            COND_JUMP done base
+            SUB m n one
+            CALL rest factorial m
+            MUL result n rest
+            RET result
+            BLOCK base
+            RET one
+        END
+
+        START
+            NUM input 5
+            CALL answer factorial input
+    ";
+
+    #[test]
+    fn vm_test_recursion() {
+        assert!(run_source(FACTORIAL, 5, "answer").unwrap() == ScalarType::Float(120.0));
+        // Parameters and locals of the callee do not leak into the caller.
+        let program = Parser::new(String::from(FACTORIAL)).assemble().unwrap();
+        let mut exec = Executor::new(program.bytecode);
+        exec.run().unwrap();
+        assert!(exec.stack.lookup(program.symbols["n"]).is_err());
+        assert_eq!(exec.stack.depth(), 1);
+    }
+
+    #[test]
+    fn vm_test_call_frame_hides_caller() {
+        // A function sees the globals bound before START and its parameters, not its caller's variables.
+        let src = "
+            NUM g 2
+            FUNC twice
+                ADD out g g
+                STDOUT out
+                RET out
+            END
+            START
+                CALL four twice
+                BEGIN_SCOPE
+                    NUM g 3
+                    CALL still twice
+                END_SCOPE
+                STR x \"hi\"
+                FUNC show
+                    STDOUT x
+                END
+                CALL nothing show
+        ";
+        let program = Parser::new(String::from(src)).assemble().unwrap();
+        let io = BufferIo::new();
+        let mut exec = Executor::new(program.bytecode).with_io(Box::new(io.clone()));
+        let err = exec.run().unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::UnknownMemory(program.symbols["x"]));
+        assert_eq!(err.opcode, bc::STDOUT);
+        assert_eq!(io.output(), "44");
+    }
+
+    #[test]
+    fn vm_test_stack_overflow() {
+        let err = run_source(FACTORIAL, 4, "answer").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::StackOverflow(4));
+        assert_eq!(err.opcode, bc::CALL);
+    }
+
+    #[test]
+    fn vm_test_call_errors() {
+        let src = "
+            START
+                FUNC skipped
+                    STR never \"only when called\"
+                END
+                CALL nothing skipped
+        ";
+        assert!(matches!(run_source(src, 1, "nothing").unwrap(), ScalarType::None));
+        let skipped = "START\nSTR marker \"outside\"\nFUNC skipped\nSTR marker \"inside\"\nEND\n";
+        assert!(run_source(skipped, 1, "marker").unwrap() == ScalarType::Str(String::from("outside")));
+        assert!(matches!(run_source(src, 0, "nothing").unwrap_err().kind, VmErrorKind::StackOverflow(0)));
+
+        let err = run_source("FUNC pair a b\nEND\nSTART\nNUM one 1\nCALL out pair one\n", 1, "out").unwrap_err();
+        assert!(err.kind.to_string().ends_with("expects 2 arguments, found 1"), "{}", err);
+
+        let err = run_source("START\nNUM one 1\nRET one\n", 1, "one").unwrap_err();
+        assert_eq!(err.opcode, bc::RET);
+    }
+
+    #[test]
+    fn vm_test_integers() {
+        let src = "
+            START
+                INT big 16777217
+                INT one 1
+                ADD next big one
+                INT seven 7
+                INT two 2
+                DIV half seven two
+                NUM point 2.9
+                CAST_INT truncated point
+                STR text \" -12 \"
+                CAST_INT parsed text
+                CAST_FLOAT widened seven
+                STR decimal \"2.5\"
+                CAST_NUM back decimal
+                EQ same two point
+                LT less two point
+        ";
+        assert!(run_source(src, 1, "next").unwrap() == ScalarType::Int(16777218));
+        assert!(matches!(run_source(src, 1, "half").unwrap(), ScalarType::Int(3)));
+        assert!(matches!(run_source(src, 1, "truncated").unwrap(), ScalarType::Int(2)));
+        assert!(matches!(run_source(src, 1, "parsed").unwrap(), ScalarType::Int(-12)));
+        assert!(matches!(run_source(src, 1, "widened").unwrap(), ScalarType::Float(7.0)));
+        assert!(run_source(src, 1, "back").unwrap() == ScalarType::Float(2.5));
+        assert!(run_source(src, 1, "same").unwrap() == ScalarType::Bool(false));
+        assert!(run_source(src, 1, "less").unwrap() == ScalarType::Bool(true));
+
+        let err = run_source("START\nSTR text \"1.5\"\nCAST_INT bad text\n", 1, "bad").unwrap_err();
+        assert!(matches!(err.kind, VmErrorKind::TypeMismatch(_)), "{}", err);
+        let err = run_source("START\nNUM huge 1e19\nCAST_INT bad huge\n", 1, "bad").unwrap_err();
+        assert!(matches!(err.kind, VmErrorKind::TypeMismatch(_)), "{}", err);
+    }
+
+    #[test]
+    fn vm_test_num_round_trip() {
+        let values = [0.1 + 0.2, -1.289893, f64::MAX, f64::MIN_POSITIVE, 5e-324, -0.0, 1e300, f64::INFINITY, f64::NEG_INFINITY];
+        let mut bb = BytecodeBuilder::new();
+        let ids:Vec<u32> = values.iter().map(|value| bb.write_num(*value, None)).collect();
+        let nan = bb.write_num(f64::NAN, None);
+        let mut exec = Executor::new(bb.src);
+        exec.run().unwrap();
+        for (value, id) in values.iter().zip(ids) {
+            match exec.stack.lookup(id).unwrap() {
+                ScalarType::Float(read) => assert_eq!(read.to_bits(), value.to_bits(), "{} read back as {}", value, read),
+                other => panic!("{} read back as {}", value, other),
             }
         }
+        assert!(matches!(exec.stack.lookup(nan).unwrap(), ScalarType::Float(read) if read.is_nan()));
+    }
+
+    #[test]
+    fn vm_test_integer_overflow() {
+        let err = run_source("START\nINT max 9223372036854775807\nINT one 1\nADD out max one\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Overflow(String::from("ADD")));
+        assert_eq!(err.opcode, bc::ADD);
+
+        let err = run_source("START\nINT min -9223372036854775808\nINT neg -1\nDIV out min neg\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Overflow(String::from("DIV")));
+
+        let max = run_source("START\nINT max 9223372036854775807\nNUM one 1\nADD out max one\n", 1, "out").unwrap();
+        assert!(max == ScalarType::Float(9223372036854775808.0));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn vm_test_logical() {
+        let src = "
+            START
+                INT one 1
+                INT two 2
+                LT small one two
+                GT big one two
+                AND both small big
+                OR either small big
+                NOT neither either
+                BOOL jumped true
+                COND_JUMP either done
+                BOOL jumped false
+                BLOCK done
+        ";
+        assert!(run_source(src, 1, "both").unwrap() == ScalarType::Bool(false));
+        assert!(run_source(src, 1, "either").unwrap() == ScalarType::Bool(true));
+        assert!(run_source(src, 1, "neither").unwrap() == ScalarType::Bool(false));
+        assert!(run_source(src, 1, "jumped").unwrap() == ScalarType::Bool(true));
+
+        let err = run_source("START\nBOOL yes true\nINT one 1\nAND out yes one\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot AND bool and int")));
+        assert_eq!(err.opcode, bc::AND);
+        let err = run_source("START\nSTR text \"x\"\nNOT out text\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot NOT str")));
+    }
+
+    #[test]
+    fn vm_test_bitwise() {
+        let src = "
+            START
+                INT twelve 12
+                INT ten 10
+                INT two 2
+                INT negative -16
+                BAND and twelve ten
+                BOR or twelve ten
+                BXOR xor twelve ten
+                SHL left twelve two
+                SHR right negative two
+        ";
+        assert!(matches!(run_source(src, 1, "and").unwrap(), ScalarType::Int(8)));
+        assert!(matches!(run_source(src, 1, "or").unwrap(), ScalarType::Int(14)));
+        assert!(matches!(run_source(src, 1, "xor").unwrap(), ScalarType::Int(6)));
+        assert!(matches!(run_source(src, 1, "left").unwrap(), ScalarType::Int(48)));
+        assert!(matches!(run_source(src, 1, "right").unwrap(), ScalarType::Int(-4)));
+
+        let err = run_source("START\nINT one 1\nNUM half 0.5\nBOR out one half\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot BOR int and float")));
+        assert_eq!(err.opcode, bc::BOR);
+        let err = run_source("START\nBOOL yes true\nBXOR out yes yes\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot BXOR bool and bool")));
+        let err = run_source("START\nINT one 1\nINT far 64\nSHL out one far\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Overflow(String::from("SHL")));
+        let err = run_source("START\nINT one 1\nINT back -1\nSHR out one back\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Overflow(String::from("SHR")));
+    }
+
+    #[test]
+    fn vm_test_math_intrinsics() {
+        let src = "
+            START
+                INT five 5
+                INT three 3
+                NUM half -2.5
+                NUM four 4
+                NUM zero 0
+                BOOL yes true
+                NEG minus five
+                NEG flipped half
+                NEG negated yes
+                ABS positive half
+                FLOOR down half
+                CEIL up half
+                ROUND away half
+                ROUND whole five
+                SQRT root four
+                SQRT from_int five
+                SIN sine zero
+                COS cosine zero
+                LOG ln four
+                MIN least five three
+                MAX most five half
+                MIN mixed yes five
+                MAX bools yes yes
+                EXP power yes three
+        ";
+        assert!(matches!(run_source(src, 1, "minus").unwrap(), ScalarType::Int(-5)));
+        assert!(run_source(src, 1, "flipped").unwrap() == ScalarType::Float(2.5));
+        assert!(matches!(run_source(src, 1, "negated").unwrap(), ScalarType::Int(-1)));
+        assert!(run_source(src, 1, "positive").unwrap() == ScalarType::Float(2.5));
+        assert!(matches!(run_source(src, 1, "down").unwrap(), ScalarType::Float(-3.0)));
+        assert!(matches!(run_source(src, 1, "up").unwrap(), ScalarType::Float(-2.0)));
+        assert!(matches!(run_source(src, 1, "away").unwrap(), ScalarType::Float(-3.0)));
+        assert!(matches!(run_source(src, 1, "whole").unwrap(), ScalarType::Int(5)));
+        assert!(matches!(run_source(src, 1, "root").unwrap(), ScalarType::Float(2.0)));
+        assert!(run_source(src, 1, "from_int").unwrap() == ScalarType::Float(5.0f64.sqrt()));
+        assert!(matches!(run_source(src, 1, "sine").unwrap(), ScalarType::Float(0.0)));
+        assert!(matches!(run_source(src, 1, "cosine").unwrap(), ScalarType::Float(1.0)));
+        assert!(run_source(src, 1, "ln").unwrap() == ScalarType::Float(4.0f64.ln()));
+        assert!(matches!(run_source(src, 1, "least").unwrap(), ScalarType::Int(3)));
+        assert!(matches!(run_source(src, 1, "most").unwrap(), ScalarType::Float(5.0)));
+        assert!(matches!(run_source(src, 1, "mixed").unwrap(), ScalarType::Int(1)));
+        assert!(matches!(run_source(src, 1, "bools").unwrap(), ScalarType::Float(1.0)));
+        assert!(matches!(run_source(src, 1, "power").unwrap(), ScalarType::Float(1.0)));
+    }
+
+    #[test]
+    fn vm_test_math_errors() {
+        let err = run_source("START\nSTR text \"x\"\nSQRT out text\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot SQRT str")));
+        assert_eq!(err.opcode, bc::SQRT);
+        let err = run_source("START\nSTR text \"x\"\nINT one 1\nEXP out text one\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot EXP str and int")));
+        let err = run_source("START\nLIST items\nINT one 1\nMAX out items one\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::TypeMismatch(String::from("cannot MAX list and int")));
+        let err = run_source("START\nNUM neg -1\nSQRT out neg\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Domain(String::from("SQRT of -1")));
+        let err = run_source("START\nINT zero 0\nLOG out zero\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Domain(String::from("LOG of 0")));
+        assert!(err.to_string().ends_with("math domain error: LOG of 0"), "{}", err);
+        let err = run_source("START\nINT min -9223372036854775808\nABS out min\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Overflow(String::from("ABS")));
+        let err = run_source("START\nINT min -9223372036854775808\nNEG out min\n", 1, "out").unwrap_err();
+        assert_eq!(err.kind, VmErrorKind::Overflow(String::from("NEG")));
+    }
+
+    #[test]
+    fn vm_test_ids_colliding_with_constants() {
+        let mut bb = BytecodeBuilder::new();
+        let greeting = bb.write_str(String::from("hi"), None);
+        // The next id is the string constant, reused here as a variable.
+        let clash = bb.write_num(2.0, Some(greeting + 1));
+        // And the one after is the number constant, reused as a string variable.
+        let text = bb.write_str(String::from("{} {}"), Some(clash + 1));
+        // Ids equal to opcodes must not be mistaken for instructions either.
+        let block = bb.write_num(3.0, Some(bc::BLOCK));
+        bb.write_start();
+        let end = bb.write_bool(true, Some(bc::END));
+        let joined = bb.write_fmt(text, vec![greeting, clash], None);
+        let bytes = bb.src.serialize();
+
+        let mut exec = Executor::from_bytes(&bytes).unwrap();
+        exec.run().unwrap();
+        assert!(exec.stack.lookup(greeting).unwrap() == ScalarType::Str(String::from("hi")));
+        assert!(exec.stack.lookup(clash).unwrap() == ScalarType::Float(2.0));
+        assert!(exec.stack.lookup(joined).unwrap() == ScalarType::Str(String::from("hi 2")));
+        assert!(exec.stack.lookup(block).unwrap() == ScalarType::Float(3.0));
+        assert!(exec.stack.lookup(end).unwrap() == ScalarType::Bool(true));
+    }
+
+    #[test]
+    fn vm_test_malformed_instruction() {
+        let mut bb = BytecodeBuilder::new();
+        bb.write_start();
+        let num = bb.write_num(1.0, None);
+        bb.write_add(num, num, None);
+        let last = bb.src.len() - 1;
+        bb.src.set(last, bc::START);
+        let err = Executor::new(bb.src).run().unwrap_err();
+        assert!(matches!(err.kind, VmErrorKind::MalformedInstruction(_)));
+        assert_eq!(err.opcode, bc::ADD);
+    }
+
+    #[test]
+    fn vm_test_dyn_format() {
+        let arguments = vec![String::from("1"), String::from("2")];
+        assert_eq!(Executor::dyn_format(String::from("{} + {}!"), arguments), Ok(String::from("1 + 2!")));
+    }
+}
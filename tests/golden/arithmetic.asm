@@ -0,0 +1,49 @@
+START
+    NUM seven 7
+    NUM two 2
+    INT three 3
+    INT four 4
+    STR nl "\n"
+    STR sep " "
+
+    ADD sum seven two
+    SUB diff seven two
+    MUL prod seven two
+    DIV quot seven two
+    MOD rem seven two
+    EXP pow seven two
+    STDOUT sum
+    STDOUT sep
+    STDOUT diff
+    STDOUT sep
+    STDOUT prod
+    STDOUT sep
+    STDOUT quot
+    STDOUT sep
+    STDOUT rem
+    STDOUT sep
+    STDOUT pow
+    STDOUT nl
+
+    # Ints stay ints until a float is involved.
+    ADD isum three four
+    DIV iquot four three
+    MUL mixed three two
+    STDOUT isum
+    STDOUT sep
+    STDOUT iquot
+    STDOUT sep
+    STDOUT mixed
+    STDOUT nl
+
+    STR a "abc"
+    STR b "b"
+    ADD joined a b
+    SUB removed a b
+    ADD labelled a three
+    STDOUT joined
+    STDOUT sep
+    STDOUT removed
+    STDOUT sep
+    STDOUT labelled
+    STDOUT nl
@@ -0,0 +1,3 @@
+START
+    NUM a 1
+    ADD b a missing
@@ -0,0 +1,24 @@
+START
+    STR nl "\n"
+    STR decimal " 3.75 "
+    CAST_NUM num decimal
+    NUM quarter 0.25
+    ADD whole num quarter
+    STDOUT whole
+    STDOUT nl
+
+    STR digits "-12"
+    CAST_INT int digits
+    CAST_FLOAT float int
+    CAST_STR text float
+    ADD again text text
+    STDOUT again
+    STDOUT nl
+
+    NUM pi 3.14159
+    CAST_INT truncated pi
+    NUM precision 2
+    FMT_NUM rounded pi precision
+    STR template "{} is about {} or {}\n"
+    FMT line template pi rounded truncated
+    STDOUT line
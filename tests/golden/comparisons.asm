@@ -0,0 +1,34 @@
+START
+    INT one 1
+    NUM two 2
+    STR sep " "
+    STR nl "\n"
+    BOOL yes true
+
+    EQ eq one two
+    NEQ neq one two
+    GT gt one two
+    GTE gte two two
+    LT lt one two
+    LTE lte two one
+    STDOUT eq
+    STDOUT sep
+    STDOUT neq
+    STDOUT sep
+    STDOUT gt
+    STDOUT sep
+    STDOUT gte
+    STDOUT sep
+    STDOUT lt
+    STDOUT sep
+    STDOUT lte
+    STDOUT nl
+
+    STR apple "apple"
+    STR pear "pear"
+    LT order apple pear
+    EQ same yes yes
+    STDOUT order
+    STDOUT sep
+    STDOUT same
+    STDOUT nl
@@ -0,0 +1,19 @@
+START
+    INT ind 0
+    INT sum 0
+    INT one 1
+    INT limit 5
+    STR nl "\n"
+
+    BLOCK loop
+        ADD sum sum ind
+        ADD ind ind one
+        LT again ind limit
+        COND_JUMP again loop
+
+    STDOUT sum
+    STDOUT nl
+    JUMP done
+    STR skipped "never printed\n"
+    STDOUT skipped
+    BLOCK done
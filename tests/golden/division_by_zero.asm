@@ -0,0 +1,7 @@
+START
+    STR before "printed before the error\n"
+    STDOUT before
+    INT seven 7
+    INT zero 0
+    DIV broken seven zero
+    STDOUT broken
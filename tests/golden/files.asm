@@ -0,0 +1,14 @@
+START
+    STR path "target/golden-files.txt"
+    STR write "w"
+    STR read "r"
+    STR first "first line\n"
+    NUM second 2
+    OPEN out path write
+    WRITE out first
+    WRITE out second
+    CLOSE out
+    OPEN in path read
+    READ contents in
+    CLOSE in
+    STDOUT contents
@@ -0,0 +1,29 @@
+INT one 1
+STR nl "\n"
+
+FUNC fact n
+    LTE small n one
+    COND_JUMP small base
+    SUB less n one
+    CALL rest fact less
+    MUL out n rest
+    RET out
+    BLOCK base
+    RET one
+END
+
+FUNC greet name
+    STR template "hello {}\n"
+    FMT line template name
+    STDOUT line
+END
+
+START
+    INT ten 10
+    CALL result fact ten
+    STDOUT result
+    STDOUT nl
+    STR who "functions"
+    CALL nothing greet who
+    STDOUT nothing
+    STDOUT nl
@@ -0,0 +1,3 @@
+START
+    STR hello "Hello world!\n"
+    STDOUT hello
@@ -0,0 +1,19 @@
+START
+    INT zero 0
+    INT one 1
+    INT two 2
+    STR nl "\n"
+    LIST row zero one
+    LIST grid row
+    PUSH row two
+    INDEX last grid zero two
+    STDOUT grid
+    STDOUT nl
+    STORE_INDEX grid two zero zero
+    POP top row
+    STDOUT top
+    STDOUT nl
+    STDOUT grid
+    STDOUT nl
+    STDOUT last
+    STDOUT nl
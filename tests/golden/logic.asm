@@ -0,0 +1,35 @@
+START
+    BOOL yes true
+    BOOL no false
+    INT twelve 12
+    INT ten 10
+    INT two 2
+    INT negative -16
+    STR sep " "
+    STR nl "\n"
+
+    AND and yes no
+    OR or yes no
+    NOT not or
+    STDOUT and
+    STDOUT sep
+    STDOUT or
+    STDOUT sep
+    STDOUT not
+    STDOUT nl
+
+    BAND band twelve ten
+    BOR bor twelve ten
+    BXOR bxor twelve ten
+    SHL shl twelve two
+    SHR shr negative two
+    STDOUT band
+    STDOUT sep
+    STDOUT bor
+    STDOUT sep
+    STDOUT bxor
+    STDOUT sep
+    STDOUT shl
+    STDOUT sep
+    STDOUT shr
+    STDOUT nl
@@ -0,0 +1,41 @@
+START
+    INT five 5
+    NUM half -2.5
+    NUM four 4
+    NUM zero 0
+    STR sep " "
+    STR nl "\n"
+
+    NEG neg five
+    ABS abs half
+    FLOOR floor half
+    CEIL ceil half
+    ROUND round half
+    SQRT sqrt four
+    SIN sin zero
+    COS cos zero
+    LOG log four
+    MIN min five half
+    MAX max five four
+    STDOUT neg
+    STDOUT sep
+    STDOUT abs
+    STDOUT sep
+    STDOUT floor
+    STDOUT sep
+    STDOUT ceil
+    STDOUT sep
+    STDOUT round
+    STDOUT sep
+    STDOUT sqrt
+    STDOUT sep
+    STDOUT sin
+    STDOUT sep
+    STDOUT cos
+    STDOUT sep
+    STDOUT log
+    STDOUT sep
+    STDOUT min
+    STDOUT sep
+    STDOUT max
+    STDOUT nl
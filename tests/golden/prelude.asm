@@ -0,0 +1,26 @@
+# Before START only literals, ALLOCA, BLOCK and FUNC run; everything else waits until a jump
+# after START brings execution back here.
+INT count 0
+INT one 1
+INT three 3
+STR tick "tick\n"
+STR nl "\n"
+ALLOCA slot
+STDOUT tick
+
+BLOCK loop
+    STDOUT tick
+    ADD count count one
+    LT again count three
+    COND_JUMP again loop
+JUMP finished
+
+START
+    STDOUT count
+    STDOUT nl
+    STDOUT slot
+    STDOUT nl
+    JUMP loop
+    BLOCK finished
+    STDOUT count
+    STDOUT nl
@@ -0,0 +1,7 @@
+FUNC forever n
+    CALL again forever n
+END
+
+START
+    INT zero 0
+    CALL never forever zero
@@ -0,0 +1,18 @@
+START
+    NUM outer 1
+    STR nl "\n"
+    ALLOCA slot
+    STORE slot outer
+    BEGIN_SCOPE
+        NUM outer 2
+        STDOUT outer
+        BEGIN_SCOPE
+            NUM outer 3
+            DEL outer
+            STDOUT outer
+        END_SCOPE
+    END_SCOPE
+    STDOUT outer
+    STDOUT slot
+    DEL slot
+    STDOUT nl
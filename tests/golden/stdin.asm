@@ -0,0 +1,11 @@
+START
+    STR template "hello {} {}!\n"
+    STDIN first
+    STDIN last
+    FMT line template first last
+    STDOUT line
+    STDIN rest
+    STR quote "'"
+    STDOUT quote
+    STDOUT rest
+    STDOUT quote
@@ -0,0 +1,4 @@
+START
+    INT twelve 12
+    NUM half 0.5
+    BAND broken twelve half
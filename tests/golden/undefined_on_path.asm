@@ -0,0 +1,5 @@
+START
+    BEGIN_SCOPE
+        NUM inner 1
+    END_SCOPE
+    STDOUT inner